@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Sender};
+use chrono::{DateTime, Local, Duration};
+use lazy_static::lazy_static;
+use lettre::{ClientSecurity, ClientTlsParameters, SmtpClient, Transport};
+use lettre::smtp::authentication::Credentials;
+use lettre_email::EmailBuilder;
+use log::{info, warn, error};
+use native_tls::TlsConnector;
+use serde::{Serialize, Deserialize};
+
+use crate::config::{Secret, Live};
+
+fn default_port() -> u16 {
+  587
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Security {
+  /// Plain text, for a relay on the same network or a stand-in server.
+  None,
+  Starttls,
+  /// TLS from the start, usually on port 465.
+  Tls,
+}
+
+impl Default for Security {
+  fn default() -> Security {
+    Security::Starttls
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EmailSettings {
+  pub server: String,
+  #[serde(default = "default_port")]
+  pub port: u16,
+  #[serde(default)]
+  pub security: Security,
+  pub username: Option<String>,
+  pub password: Option<Secret>,
+  pub from: String,
+  pub to: Vec<String>,
+}
+
+fn default_cablecast_unreachable_minutes() -> i64 {
+  5
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AlertSettings {
+  /// URLs each alert is POSTed to as JSON.
+  #[serde(default)]
+  pub webhooks: Vec<String>,
+  pub email: Option<EmailSettings>,
+  /// How long Cablecast can be unreachable before it's alerted on.
+  #[serde(default = "default_cablecast_unreachable_minutes")]
+  pub cablecast_unreachable_minutes: i64,
+}
+
+impl Default for AlertSettings {
+  fn default() -> AlertSettings {
+    AlertSettings {
+      webhooks: Vec::new(),
+      email: None,
+      cablecast_unreachable_minutes: default_cablecast_unreachable_minutes(),
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+  ForceEventFailed,
+  SwitchUnconfirmed,
+  OverrideStuck,
+  TemplateMissing,
+  OutputStopped,
+  CablecastUnreachable,
+  StartedInFallback,
+  /// Sent by `scissors test-alerts` to check the sinks.
+  Test,
+}
+
+impl fmt::Display for Kind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Kind::ForceEventFailed => write!(f, "force event failed"),
+      Kind::SwitchUnconfirmed => write!(f, "switch not confirmed"),
+      Kind::OverrideStuck => write!(f, "automation override stuck"),
+      Kind::TemplateMissing => write!(f, "template missing"),
+      Kind::OutputStopped => write!(f, "DeckLink output stopped"),
+      Kind::CablecastUnreachable => write!(f, "Cablecast unreachable"),
+      Kind::StartedInFallback => write!(f, "show started on fallback"),
+      Kind::Test => write!(f, "test alert"),
+    }
+  }
+}
+
+/// What goes to every sink, once when a problem starts and once when it's
+/// resolved.
+#[derive(Debug, Serialize, Clone)]
+pub struct Alert {
+  pub kind: Kind,
+  /// What the problem is with, usually a channel's name.
+  pub subject: String,
+  pub resolved: bool,
+  pub message: String,
+  /// When the problem started.
+  pub since: DateTime<Local>,
+  pub at: DateTime<Local>,
+}
+
+impl Alert {
+  pub fn title(&self) -> String {
+    let state = if self.resolved { "RESOLVED" } else { "ALERT" };
+    format!("[scissors] {}: {} on {}", state, self.kind, self.subject)
+  }
+
+  pub fn body(&self) -> String {
+    format!("{}\n\nSince: {}\nAt: {}\n", self.message, self.since.to_rfc2822(), self.at.to_rfc2822())
+  }
+}
+
+struct Active {
+  since: DateTime<Local>,
+  message: String,
+}
+
+lazy_static! {
+  // Problems already alerted on, so each is only sent once until it's
+  // resolved.
+  static ref ACTIVE: Mutex<HashMap<(Kind, String), Active>> = Mutex::new(HashMap::new());
+  static ref OUTBOX: Mutex<Option<Sender<Alert>>> = Mutex::new(None);
+  static ref LIVE: Mutex<Option<Live>> = Mutex::new(None);
+  static ref UNREACHABLE_SINCE: Mutex<Option<DateTime<Local>>> = Mutex::new(None);
+}
+
+/// Starts sending alerts from a background thread, to whatever sinks the
+/// config has at the time.
+pub fn start(live: Live) {
+  let (sender, receiver) = channel::<Alert>();
+  *OUTBOX.lock().unwrap() = Some(sender);
+  *LIVE.lock().unwrap() = Some(live.clone());
+
+  std::thread::spawn(move || {
+    for alert in receiver {
+      for (sink, result) in send(&live.settings().alerts, &alert) {
+        if let Err(err) = result {
+          error!("Failed to send \"{}\" to {}! {}", alert.title(), sink, err);
+        }
+      }
+    }
+  });
+}
+
+fn queue(alert: Alert) {
+  if let Some(outbox) = OUTBOX.lock().unwrap().as_ref() {
+    let _ = outbox.send(alert);
+  }
+}
+
+/// Alerts on a problem with `subject`, unless it's already been alerted on.
+pub fn raise(kind: Kind, subject: &str, message: String) {
+  let now = Local::now();
+  let mut active = ACTIVE.lock().unwrap();
+  if active.contains_key(&(kind, subject.to_string())) {
+    return;
+  }
+
+  error!("ALERT: {} on {}: {}", kind, subject, message);
+  active.insert((kind, subject.to_string()), Active { since: now, message: message.clone() });
+  queue(Alert { kind, subject: subject.to_string(), resolved: false, message, since: now, at: now });
+}
+
+/// Sends a recovery notice if there was an alert for the problem.
+pub fn resolve(kind: Kind, subject: &str) {
+  let active = match ACTIVE.lock().unwrap().remove(&(kind, subject.to_string())) {
+    Some(active) => active,
+    None => return,
+  };
+
+  let now = Local::now();
+  let minutes = now.signed_duration_since(active.since).num_minutes();
+  info!("Resolved: {} on {} after {} minutes", kind, subject, minutes);
+  queue(Alert {
+    kind,
+    subject: subject.to_string(),
+    resolved: true,
+    message: format!("Recovered after {} minutes. It started with: {}", minutes, active.message),
+    since: active.since,
+    at: now,
+  });
+}
+
+/// Keeps track of how long Cablecast has been unreachable, alerting once
+/// it's been too long.
+pub fn cablecast_polled(ok: bool) {
+  let mut since = UNREACHABLE_SINCE.lock().unwrap();
+  if ok {
+    *since = None;
+    resolve(Kind::CablecastUnreachable, "Cablecast");
+    return;
+  }
+
+  let now = Local::now();
+  let since = *since.get_or_insert(now);
+  let minutes = LIVE.lock().unwrap().as_ref()
+    .map_or(default_cablecast_unreachable_minutes(), |x| x.settings().alerts.cablecast_unreachable_minutes);
+  if now.signed_duration_since(since) >= Duration::minutes(minutes) {
+    raise(Kind::CablecastUnreachable, "Cablecast", format!("Cablecast hasn't answered since {}", since.to_rfc2822()));
+  }
+}
+
+// Alerts that can't be delivered in this long are given up on, so a dead
+// sink doesn't hold up the rest.
+const SEND_TIMEOUT_SECS: u64 = 10;
+
+fn send_webhook(url: &str, alert: &Alert) -> Result<(), Box<dyn Error>> {
+  reqwest::blocking::Client::new()
+    .post(url)
+    .timeout(std::time::Duration::from_secs(SEND_TIMEOUT_SECS))
+    .json(alert)
+    .send()?
+    .error_for_status()?;
+
+  Ok(())
+}
+
+fn tls(server: &str) -> Result<ClientTlsParameters, Box<dyn Error>> {
+  Ok(ClientTlsParameters::new(server.to_string(), TlsConnector::new()?))
+}
+
+fn send_email(settings: &EmailSettings, alert: &Alert) -> Result<(), Box<dyn Error>> {
+  let mut builder = EmailBuilder::new()
+    .from(settings.from.as_str())
+    .subject(alert.title())
+    .text(alert.body());
+  for to in &settings.to {
+    builder = builder.to(to.as_str());
+  }
+  let email = builder.build()?;
+
+  let security = match settings.security {
+    Security::None => ClientSecurity::None,
+    Security::Starttls => ClientSecurity::Required(tls(&settings.server)?),
+    Security::Tls => ClientSecurity::Wrapper(tls(&settings.server)?),
+  };
+
+  let mut client = SmtpClient::new((settings.server.as_str(), settings.port), security)?
+    .timeout(Some(std::time::Duration::from_secs(SEND_TIMEOUT_SECS)));
+  if let (Some(username), Some(password)) = (&settings.username, &settings.password) {
+    client = client.credentials(Credentials::new(username.clone(), password.expose().to_string()));
+  }
+
+  client.transport().send(email.into())?;
+  Ok(())
+}
+
+/// Sends an alert to every sink right away, returning how each one went.
+pub fn send(settings: &AlertSettings, alert: &Alert) -> Vec<(String, Result<(), Box<dyn Error>>)> {
+  let mut results = Vec::new();
+  for url in &settings.webhooks {
+    results.push((format!("webhook {}", url), send_webhook(url, alert)));
+  }
+
+  if let Some(email) = &settings.email {
+    results.push((format!("email via {}:{}", email.server, email.port), send_email(email, alert)));
+  }
+
+  if results.is_empty() {
+    warn!("No alert sinks are set up, \"{}\" went nowhere", alert.title());
+  }
+
+  results
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::{BufRead, BufReader, Write};
+  use std::net::TcpListener;
+  use std::sync::mpsc::Receiver;
+  use serde_json::Value;
+
+  fn alert(kind: Kind) -> Alert {
+    let now = Local::now();
+    Alert { kind, subject: "Channel 1".to_string(), resolved: false, message: "It broke".to_string(), since: now, at: now }
+  }
+
+  fn settings(webhooks: Vec<String>, email: Option<EmailSettings>) -> AlertSettings {
+    AlertSettings { webhooks, email, cablecast_unreachable_minutes: 5 }
+  }
+
+  // Answers `count` POSTs on a local port, passing on each body.
+  fn webhook_stand_in(count: usize) -> (String, Receiver<Value>) {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", server.server_addr());
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+      for _ in 0..count {
+        let mut request = server.recv().unwrap();
+        let mut body = String::new();
+        request.as_reader().read_to_string(&mut body).unwrap();
+        sender.send(serde_json::from_str(&body).unwrap()).unwrap();
+        request.respond(tiny_http::Response::empty(200)).unwrap();
+      }
+    });
+
+    (url, receiver)
+  }
+
+  // Takes one plain text SMTP session on a local port, passing on the
+  // message it was sent.
+  fn smtp_stand_in() -> (u16, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+      let (stream, _) = listener.accept().unwrap();
+      let mut reader = BufReader::new(stream.try_clone().unwrap());
+      let mut writer = stream;
+      writer.write_all(b"220 stand-in\r\n").unwrap();
+
+      let mut line = String::new();
+      while reader.read_line(&mut line).unwrap() > 0 {
+        let command = line.trim_end().to_ascii_uppercase();
+        if command.starts_with("DATA") {
+          writer.write_all(b"354 go ahead\r\n").unwrap();
+          let mut message = String::new();
+          loop {
+            let mut data = String::new();
+            reader.read_line(&mut data).unwrap();
+            if data == ".\r\n" {
+              break;
+            }
+            message.push_str(&data);
+          }
+          sender.send(message).unwrap();
+          writer.write_all(b"250 queued\r\n").unwrap();
+        } else if command.starts_with("QUIT") {
+          writer.write_all(b"221 bye\r\n").unwrap();
+          break;
+        } else {
+          writer.write_all(b"250 ok\r\n").unwrap();
+        }
+        line.clear();
+      }
+    });
+
+    (port, receiver)
+  }
+
+  #[test]
+  fn webhook_gets_the_alert_as_json() {
+    let (url, received) = webhook_stand_in(1);
+    let results = send(&settings(vec![url], None), &alert(Kind::ForceEventFailed));
+    assert_eq!(results.len(), 1);
+    assert!(results[0].1.is_ok(), "{:?}", results[0].1.as_ref().err().map(|x| x.to_string()));
+
+    let payload = received.recv().unwrap();
+    assert_eq!(payload["kind"], "force_event_failed");
+    assert_eq!(payload["subject"], "Channel 1");
+    assert_eq!(payload["resolved"], false);
+    assert_eq!(payload["message"], "It broke");
+  }
+
+  #[test]
+  fn email_gets_the_alert_as_text() {
+    let (port, received) = smtp_stand_in();
+    let email = EmailSettings {
+      server: "127.0.0.1".to_string(),
+      port,
+      security: Security::None,
+      username: None,
+      password: None,
+      from: "scissors@example.org".to_string(),
+      to: vec!["master-control@example.org".to_string()],
+    };
+
+    let results = send(&settings(Vec::new(), Some(email)), &alert(Kind::OutputStopped));
+    assert_eq!(results.len(), 1);
+    assert!(results[0].1.is_ok(), "{:?}", results[0].1.as_ref().err().map(|x| x.to_string()));
+
+    let message = received.recv().unwrap();
+    assert!(message.contains("Subject: [scissors] ALERT: DeckLink output stopped on Channel 1"), "{}", message);
+    assert!(message.contains("To: <master-control@example.org>"), "{}", message);
+    assert!(message.contains("It broke"), "{}", message);
+  }
+
+  #[test]
+  fn problems_alert_once_until_resolved() {
+    let (sender, outbox) = channel();
+    *OUTBOX.lock().unwrap() = Some(sender);
+
+    raise(Kind::TemplateMissing, "Dedup test", "No template".to_string());
+    raise(Kind::TemplateMissing, "Dedup test", "Still no template".to_string());
+    resolve(Kind::TemplateMissing, "Dedup test");
+    resolve(Kind::TemplateMissing, "Dedup test");
+
+    let queued: Vec<Alert> = outbox.try_iter().filter(|x| x.subject == "Dedup test").collect();
+    assert_eq!(queued.len(), 2);
+
+    let (url, received) = webhook_stand_in(2);
+    let settings = settings(vec![url], None);
+    for alert in &queued {
+      assert!(send(&settings, alert).into_iter().all(|(_, result)| result.is_ok()));
+    }
+
+    let alerted = received.recv().unwrap();
+    assert_eq!(alerted["resolved"], false);
+    assert_eq!(alerted["message"], "No template");
+
+    let resolved = received.recv().unwrap();
+    assert_eq!(resolved["resolved"], true);
+    assert!(resolved["message"].as_str().unwrap().ends_with("It started with: No template"));
+  }
+}
@@ -0,0 +1,241 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions, rename};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use chrono::{DateTime, FixedOffset, Local};
+use lazy_static::lazy_static;
+use log::{info, error};
+use serde::Serialize;
+
+/// One try at posting a force event.
+#[derive(Debug, Serialize, Clone)]
+pub struct Attempt {
+  pub at: DateTime<Local>,
+  pub status: Option<u16>,
+  pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ForceEventResult {
+  pub at: DateTime<Local>,
+  pub event: String,
+  pub dry_run: bool,
+  pub attempts: Vec<Attempt>,
+  pub ok: bool,
+  pub error: Option<String>,
+}
+
+/// What happened to one event on one channel.
+#[derive(Debug, Serialize, Clone)]
+pub struct Record {
+  pub logged_at: DateTime<Local>,
+  pub channel: String,
+  pub output: i64,
+  pub show: i64,
+  pub title: String,
+  pub scheduled_start: DateTime<FixedOffset>,
+  pub scheduled_end: DateTime<FixedOffset>,
+  /// Where one reel hands over to the next.
+  pub cue_points: Vec<DateTime<FixedOffset>>,
+  pub actual_start: Option<DateTime<Local>>,
+  pub actual_end: Option<DateTime<Local>>,
+  pub template: Option<String>,
+  pub level: u8,
+  pub decision: String,
+  pub force_events: Vec<ForceEventResult>,
+  pub switch_confirmed: Option<bool>,
+  pub errors: Vec<String>,
+  /// The operator request this line records, rather than the show airing.
+  pub action: Option<String>,
+}
+
+// The record each channel thread is filling in, and the force event it's
+// in the middle of sending.
+thread_local! {
+  static CURRENT: RefCell<Option<Record>> = RefCell::new(None);
+  static FORCE_EVENT: RefCell<Option<ForceEventResult>> = RefCell::new(None);
+}
+
+lazy_static! {
+  // Channels finish events on their own threads, and their lines mustn't
+  // interleave.
+  static ref WRITER: Mutex<()> = Mutex::new(());
+}
+
+fn path(extension: &str) -> PathBuf {
+  let mut path = dirs::document_dir().unwrap();
+  path.push(format!("scissors-as-run.{}", extension));
+  path
+}
+
+const CSV_HEADER: &str = "logged_at,channel,output,show,title,scheduled_start,scheduled_end,actual_start,actual_end,template,level,decision,switch_confirmed,force_events,errors,action,cue_points";
+
+fn csv_field(field: &str) -> String {
+  format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+fn csv_line(record: &Record) -> String {
+  let time = |x: &Option<DateTime<Local>>| x.map_or(String::new(), |x| x.to_rfc3339());
+  let force_events: Vec<String> = record.force_events.iter().map(|x| {
+    let statuses: Vec<String> = x.attempts.iter()
+      .map(|attempt| attempt.status.map_or_else(|| attempt.error.clone().unwrap_or_default(), |x| x.to_string()))
+      .collect();
+    let outcome = if x.dry_run { "dry run".to_string() } else if x.ok { "ok".to_string() } else { x.error.clone().unwrap_or_default() };
+    format!("{} {} [{}] {}", x.at.to_rfc3339(), x.event, statuses.join(", "), outcome)
+  }).collect();
+
+  let fields = [
+    record.logged_at.to_rfc3339(),
+    record.channel.clone(),
+    record.output.to_string(),
+    record.show.to_string(),
+    record.title.clone(),
+    record.scheduled_start.to_rfc3339(),
+    record.scheduled_end.to_rfc3339(),
+    time(&record.actual_start),
+    time(&record.actual_end),
+    record.template.clone().unwrap_or_default(),
+    record.level.to_string(),
+    record.decision.clone(),
+    record.switch_confirmed.map_or(String::new(), |x| x.to_string()),
+    force_events.join("; "),
+    record.errors.join("; "),
+    record.action.clone().unwrap_or_default(),
+    record.cue_points.iter().map(|x| x.to_rfc3339()).collect::<Vec<_>>().join("; "),
+  ];
+
+  let fields: Vec<String> = fields.iter().map(|x| csv_field(x)).collect();
+  fields.join(",")
+}
+
+// A CSV log with other columns, from an older build, is moved aside rather
+// than appended to, so every line in the file matches its header.
+fn set_aside_stale_csv(path: &Path) -> Result<(), Box<dyn Error>> {
+  let header = match File::open(path) {
+    Ok(file) => BufReader::new(file).lines().next().transpose()?,
+    Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+    Err(err) => return Err(Box::new(err)),
+  };
+
+  if header.map_or(false, |x| x != CSV_HEADER) {
+    let stamp = Local::now().format("%Y%m%d-%H%M%S");
+    let aside = path.with_file_name(format!("{}-{}.csv", path.file_stem().unwrap().to_str().unwrap(), stamp));
+    rename(path, &aside)?;
+    info!("The as-run log's columns changed, moved the old one to {}", aside.to_str().unwrap());
+  }
+
+  Ok(())
+}
+
+/// Appends a record to both as-run logs.
+pub fn write(record: &Record) -> Result<(), Box<dyn Error>> {
+  let _lock = WRITER.lock().unwrap();
+
+  set_aside_stale_csv(&path("csv"))?;
+  let mut csv = OpenOptions::new().create(true).append(true).open(path("csv"))?;
+  if csv.metadata()?.len() == 0 {
+    writeln!(csv, "{}", CSV_HEADER)?;
+  }
+  writeln!(csv, "{}", csv_line(record))?;
+
+  let mut jsonl = OpenOptions::new().create(true).append(true).open(path("jsonl"))?;
+  writeln!(jsonl, "{}", serde_json::to_string(record)?)?;
+
+  Ok(())
+}
+
+/// Starts collecting what happens on this thread into `record`, finishing any
+/// record that was already open.
+pub fn begin(record: Record) {
+  finish();
+  CURRENT.with(|x| *x.borrow_mut() = Some(record));
+}
+
+/// Fills in the open record, if there is one.
+pub fn update(f: impl FnOnce(&mut Record)) {
+  CURRENT.with(|x| {
+    if let Some(record) = x.borrow_mut().as_mut() {
+      f(record);
+    }
+  });
+}
+
+pub fn error(err: &dyn Display) {
+  let err = err.to_string();
+  update(|record| record.errors.push(err));
+}
+
+/// Writes out the open record, if there is one.
+pub fn finish() {
+  let record = CURRENT.with(|x| x.borrow_mut().take());
+  if let Some(mut record) = record {
+    record.logged_at = Local::now();
+    if let Err(err) = write(&record) {
+      error!("Failed to write the as-run log! {}", err);
+    }
+  }
+}
+
+pub fn force_event_started(event: String, dry_run: bool) {
+  FORCE_EVENT.with(|x| *x.borrow_mut() = Some(ForceEventResult {
+    at: Local::now(),
+    event,
+    dry_run,
+    attempts: Vec::new(),
+    ok: false,
+    error: None,
+  }));
+}
+
+pub fn force_event_attempt(status: Option<u16>, error: Option<String>) {
+  FORCE_EVENT.with(|x| {
+    if let Some(force_event) = x.borrow_mut().as_mut() {
+      force_event.attempts.push(Attempt { at: Local::now(), status, error });
+    }
+  });
+}
+
+pub fn force_event_finished(result: &Result<(), Box<dyn Error>>) {
+  let force_event = FORCE_EVENT.with(|x| x.borrow_mut().take());
+  if let Some(mut force_event) = force_event {
+    force_event.ok = result.is_ok();
+    force_event.error = result.as_ref().err().map(|x| x.to_string());
+    update(|record| record.force_events.push(force_event));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn stale_csv_is_set_aside() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("scissors-as-run.csv");
+    std::fs::write(&path, "logged_at,channel\n\"a\",\"b\"\n").unwrap();
+
+    set_aside_stale_csv(&path).unwrap();
+
+    assert!(!path.exists());
+    let aside: Vec<PathBuf> = std::fs::read_dir(dir.path()).unwrap().map(|x| x.unwrap().path()).collect();
+    assert_eq!(aside.len(), 1);
+    assert!(aside[0].file_name().unwrap().to_str().unwrap().starts_with("scissors-as-run-"));
+    assert_eq!(std::fs::read_to_string(&aside[0]).unwrap(), "logged_at,channel\n\"a\",\"b\"\n");
+  }
+
+  #[test]
+  fn current_csv_is_kept() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("scissors-as-run.csv");
+    std::fs::write(&path, format!("{}\n", CSV_HEADER)).unwrap();
+
+    set_aside_stale_csv(&path).unwrap();
+    set_aside_stale_csv(&dir.path().join("missing.csv")).unwrap();
+
+    assert!(path.exists());
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+  }
+}
@@ -0,0 +1,625 @@
+use std::fmt;
+use std::error::Error;
+use std::fs::{File, remove_file, read_dir};
+use std::path::PathBuf;
+use std::collections::HashMap;
+use std::time::Instant;
+use log::{info, warn, error};
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, FixedOffset, Local, Duration};
+use reqwest::StatusCode;
+use reqwest::blocking::Response;
+use reqwest::header::DATE;
+use rand::Rng;
+
+use crate::config::{Settings, ChannelSettings};
+use crate::asrun;
+use crate::events::{self, Event};
+use crate::metrics;
+use crate::alerts::{self, Kind as AlertKind};
+use crate::logging;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventSummaries {
+  pub event_summaries: Vec<EventSummary>,
+  pub shows: Vec<Show>,
+  pub digital_files: Vec<DigitalFile>,
+  #[serde(default)]
+  pub reels: Vec<Reel>,
+  #[serde(default)]
+  pub media: Vec<Media>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventSummary {
+  pub location: i64,
+  pub channel: i64,
+  pub show: i64,
+  pub start: DateTime<FixedOffset>,
+  pub end: DateTime<FixedOffset>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Show {
+  pub id: i64,
+  pub cg_title: String,
+  pub project: Option<i64>,
+  pub event_date: DateTime<FixedOffset>,
+  #[serde(default)]
+  pub reels: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DigitalFile {
+  pub show: i64,
+  pub aspect_ratio: i64,
+}
+
+/// One segment of a show's content, `cue` seconds into `media` and running
+/// for `length` seconds. Reels that haven't been filled in yet can be
+/// missing either.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reel {
+  pub id: i64,
+  pub media: Option<i64>,
+  pub cue: Option<i64>,
+  pub length: Option<i64>,
+}
+
+impl Reel {
+  pub fn in_point(&self) -> Duration {
+    Duration::seconds(self.cue.unwrap_or(0))
+  }
+
+  pub fn length(&self) -> Option<Duration> {
+    self.length.map(Duration::seconds)
+  }
+}
+
+/// A file reels play from, with the part of it that's program content
+/// between `in_point` and `out_point`, in seconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Media {
+  pub id: i64,
+  #[serde(default)]
+  pub name: String,
+  pub in_point: Option<i64>,
+  pub out_point: Option<i64>,
+  pub length: Option<i64>,
+}
+
+impl Media {
+  /// Where the content ends, which is the end of the file without an out
+  /// point.
+  pub fn out_point(&self) -> Option<Duration> {
+    self.out_point.or(self.length).map(Duration::seconds)
+  }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForceEvents {
+  pub force_events: Vec<ForceEvent>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForceEvent {
+  pub switch_event: Option<SwitchEvent>,
+  pub automation_override: Option<AutomationOverride>,
+}
+
+impl fmt::Display for ForceEvent {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if let Some(switch) = &self.switch_event {
+      write!(f, "switch output {} to device {}", switch.output, switch.device)?;
+    }
+
+    if let Some(automation) = &self.automation_override {
+      if self.switch_event.is_some() {
+        write!(f, ", ")?;
+      }
+
+      write!(f, "override output {} {}", automation.output, if automation.r#override { "on" } else { "off" })?;
+    }
+
+    Ok(())
+  }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SwitchEvent {
+  pub device: i64,
+  pub output: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AutomationOverride {
+  pub r#override: bool,
+  pub output: i64,
+  pub do_last_switch_on_resume: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OutputResponse {
+  output: OutputStatus,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputStatus {
+  pub current_device: Option<i64>,
+}
+
+/// Any Cablecast record that can be referred to by name.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Named {
+  pub id: i64,
+  pub name: String,
+  #[serde(alias = "primaryLocation")]
+  pub location: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OverrideMarker {
+  output: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ForceEventFailed;
+
+impl fmt::Display for ForceEventFailed {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "the force event call failed")
+  }
+}
+
+impl Error for ForceEventFailed {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct ForceEventRejected {
+  pub status: u16,
+}
+
+impl fmt::Display for ForceEventRejected {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "the force event was rejected with status {}", self.status)
+  }
+}
+
+impl Error for ForceEventRejected {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct ForceEventDeadlineMissed;
+
+impl fmt::Display for ForceEventDeadlineMissed {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "the force event could not be sent before its deadline")
+  }
+}
+
+impl Error for ForceEventDeadlineMissed {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
+
+/// Lists the records of `kind` (such as `locations` or `outputs`).
+pub fn named(cablecast_url: &str, username: &str, password: &str, kind: &str) -> Result<Vec<Named>, Box<dyn Error>> {
+  let client = reqwest::blocking::Client::new();
+  let url = format!("https://{}/CablecastAPI/v1/{}", cablecast_url, kind);
+  let sent = Instant::now();
+  let resp = client.get(&url)
+    .basic_auth(username, Some(password))
+    .timeout(Duration::seconds(REQUEST_TIMEOUT_SECS).to_std()?)
+    .send()
+    .and_then(Response::error_for_status)
+    .and_then(|x| x.json::<HashMap<String, Vec<Named>>>());
+  metrics::cablecast_request(kind, sent, resp.is_ok());
+
+  Ok(resp?.remove(kind).unwrap_or_default())
+}
+
+// More than just the next event, so the cached schedule can carry on past
+// the current show if Cablecast goes away.
+const EVENTS_PER_CHANNEL: i64 = 10;
+
+// Estimates how far the server's clock is ahead of ours from the `Date`
+// header, taking the middle of the request as the moment it was stamped.
+fn server_offset(resp: &Response, sent: DateTime<Local>, received: DateTime<Local>) -> Option<Duration> {
+  let date = resp.headers().get(DATE)?.to_str().ok()?;
+  let server_time = DateTime::parse_from_rfc2822(date).ok()?;
+  let local_time = sent + received.signed_duration_since(sent) / 2;
+
+  Some(server_time.signed_duration_since(local_time))
+}
+
+/// Fetches the upcoming schedule, along with the server clock's offset from
+/// ours if the server sent a `Date` header.
+pub fn event_summaries(config: &Settings) -> Result<(EventSummaries, Option<Duration>), Box<dyn Error>> {
+  let url = format!("https://{}/CablecastAPI/v1/eventsummaries?future=true&include=show%2Cdigitalfile%2Cmedia%2Creel&limit_per_channel={}", config.cablecast_url, EVENTS_PER_CHANNEL);
+
+  let started = Instant::now();
+  let result = (|| {
+    let sent = Local::now();
+    let resp = reqwest::blocking::get(&url)?;
+    let offset = server_offset(&resp, sent, Local::now());
+
+    Ok((resp.json::<EventSummaries>()?, offset))
+  })();
+  metrics::cablecast_request("eventsummaries", started, result.is_ok());
+
+  result
+}
+
+// Force events are safe to repeat: a duplicate switch or override only puts
+// the router in the state it is already in. What must never happen is a
+// late retry landing after the moment it was meant for, so every attempt is
+// bounded by a deadline and its request timeout is cut short to match.
+const MAX_ATTEMPTS: u32 = 5;
+const REQUEST_TIMEOUT_SECS: i64 = 5;
+const BACKOFF_BASE_MS: i64 = 250;
+const BACKOFF_MAX_MS: i64 = 4000;
+
+enum Attempt {
+  Retryable(Box<dyn Error>),
+  Fatal(Box<dyn Error>),
+}
+
+fn send_force_events(config: &Settings, force_events: &ForceEvents, timeout: std::time::Duration) -> Result<(), Attempt> {
+  let client = reqwest::blocking::Client::new();
+  let url = format!("https://{}/CablecastAPI/v1/forceevents", config.cablecast_url);
+  let sent = Instant::now();
+  let resp = client.post(&url)
+    .basic_auth(&config.username, Some(config.password.expose()))
+    .timeout(timeout)
+    .json(force_events)
+    .send();
+
+  let resp = match resp {
+    Ok(resp) => resp,
+    Err(err) => {
+      metrics::cablecast_request("forceevents", sent, false);
+      asrun::force_event_attempt(None, Some(err.to_string()));
+      if err.is_builder() || err.is_redirect() {
+        return Err(Attempt::Fatal(Box::new(err)));
+      }
+
+      return Err(Attempt::Retryable(Box::new(err)));
+    }
+  };
+
+  let status = resp.status();
+  metrics::cablecast_request("forceevents", sent, status.is_success());
+  asrun::force_event_attempt(Some(status.as_u16()), None);
+  if status.is_success() {
+    Ok(())
+  } else if status.is_server_error() || status == StatusCode::REQUEST_TIMEOUT || status == StatusCode::TOO_MANY_REQUESTS {
+    Err(Attempt::Retryable(Box::new(ForceEventFailed)))
+  } else {
+    Err(Attempt::Fatal(Box::new(ForceEventRejected { status: status.as_u16() })))
+  }
+}
+
+fn backoff(attempt: u32) -> Duration {
+  let cap = std::cmp::min(BACKOFF_MAX_MS, BACKOFF_BASE_MS << attempt);
+  Duration::milliseconds(cap / 2 + rand::thread_rng().gen_range(0, cap / 2 + 1))
+}
+
+/// Posts a force event, retrying transient failures until `deadline`. Every
+/// attempt goes into the as-run record of the event being played.
+pub fn force_event(config: &Settings, event: ForceEvent, deadline: DateTime<Local>) -> Result<(), Box<dyn Error>> {
+  let name = event.to_string();
+  asrun::force_event_started(name.clone(), config.dry_run);
+  let result = post_force_event(config, event, deadline);
+  asrun::force_event_finished(&result);
+
+  let subject = logging::channel().unwrap_or_else(|| "scissors".to_string());
+  match &result {
+    Ok(()) => {
+      alerts::resolve(AlertKind::ForceEventFailed, &subject);
+      events::publish(Event::ForceEventSucceeded { event: name, dry_run: config.dry_run });
+    }
+    Err(err) => {
+      alerts::raise(AlertKind::ForceEventFailed, &subject, format!("Could not {}: {}", name, err));
+      events::publish(Event::ForceEventFailed { event: name, error: err.to_string() });
+    }
+  }
+
+  result
+}
+
+fn post_force_event(config: &Settings, event: ForceEvent, deadline: DateTime<Local>) -> Result<(), Box<dyn Error>> {
+  let name = event.to_string();
+  let force_events = ForceEvents {
+    force_events: vec![event],
+  };
+
+  if config.dry_run {
+    info!("DRY RUN {}: POST https://{}/CablecastAPI/v1/forceevents {}",
+      Local::now().to_rfc3339(), config.cablecast_url, serde_json::to_string(&force_events)?);
+    return Ok(());
+  }
+
+  let mut attempt = 0;
+  loop {
+    let remaining = deadline.signed_duration_since(Local::now());
+    if remaining <= Duration::zero() {
+      return Err(Box::new(ForceEventDeadlineMissed));
+    }
+
+    events::publish(Event::ForceEventSent { event: name.clone(), attempt: attempt + 1 });
+    let err = match send_force_events(config, &force_events, std::cmp::min(remaining, Duration::seconds(REQUEST_TIMEOUT_SECS)).to_std()?) {
+      Ok(()) => return Ok(()),
+      Err(Attempt::Fatal(err)) => return Err(err),
+      Err(Attempt::Retryable(err)) => err,
+    };
+
+    attempt += 1;
+    if attempt >= MAX_ATTEMPTS {
+      return Err(err);
+    }
+
+    let delay = backoff(attempt);
+    if Local::now() + delay >= deadline {
+      warn!("Force event failed ({}), no time left to retry", err);
+      return Err(Box::new(ForceEventDeadlineMissed));
+    }
+
+    warn!("Force event failed ({}), retrying in {}ms", err, delay.num_milliseconds());
+    std::thread::sleep(delay.to_std()?);
+  }
+}
+
+pub fn switch(config: &Settings, channel: &ChannelSettings, deadline: DateTime<Local>) -> Result<(), Box<dyn Error>> {
+  force_event(config, ForceEvent {
+    switch_event: Some(SwitchEvent {
+      device: channel.device,
+      output: channel.output,
+    }),
+    automation_override: None,
+  }, deadline)
+}
+
+pub fn output_status(config: &Settings, channel: &ChannelSettings, timeout: std::time::Duration) -> Result<OutputStatus, Box<dyn Error>> {
+  let client = reqwest::blocking::Client::new();
+  let url = format!("https://{}/CablecastAPI/v1/outputs/{}", config.cablecast_url, channel.output);
+  let sent = Instant::now();
+  let resp = client.get(&url)
+    .basic_auth(&config.username, Some(config.password.expose()))
+    .timeout(timeout)
+    .send()
+    .and_then(Response::error_for_status)
+    .and_then(|x| x.json::<OutputResponse>());
+  metrics::cablecast_request("outputs", sent, resp.is_ok());
+
+  Ok(resp?.output)
+}
+
+/// Polls the output until it reports `channel.device`, giving up at `until`.
+///
+/// A successful force event only means Cablecast accepted it, not that the
+/// router actually moved, so this is what tells us the switch happened.
+/// Nothing here runs past `until`, requests included, since the caller may
+/// be holding the override for as long as it takes.
+pub fn confirm_switch(config: &Settings, channel: &ChannelSettings, until: DateTime<Local>) -> Result<bool, Box<dyn Error>> {
+  let mut last_err = None;
+
+  loop {
+    let remaining = until.signed_duration_since(Local::now());
+    if remaining <= Duration::zero() {
+      break;
+    }
+
+    match output_status(config, channel, std::cmp::min(remaining, Duration::seconds(REQUEST_TIMEOUT_SECS)).to_std()?) {
+      Ok(status) if status.current_device == Some(channel.device) => return Ok(true),
+      Ok(status) => {
+        info!("Output {} is on device {:?}, waiting for {}", channel.output, status.current_device, channel.device);
+        last_err = None;
+      }
+      Err(err) => last_err = Some(err),
+    }
+
+    let remaining = until.signed_duration_since(Local::now());
+    if remaining > Duration::zero() {
+      std::thread::sleep(std::cmp::min(remaining, Duration::seconds(1)).to_std()?);
+    }
+  }
+
+  match last_err {
+    Some(err) => Err(err),
+    None => Ok(false),
+  }
+}
+
+fn set_override(config: &Settings, output: i64, value: bool, deadline: DateTime<Local>) -> Result<(), Box<dyn Error>> {
+  let result = force_event(config, ForceEvent {
+    switch_event: None,
+    automation_override: Some(AutomationOverride {
+      r#override: value,
+      output,
+      do_last_switch_on_resume: false,
+    }),
+  }, deadline);
+
+  // Failing to hand the output back leaves it stuck out of automation.
+  let subject = format!("output {}", output);
+  match &result {
+    Err(err) if !value => alerts::raise(AlertKind::OverrideStuck, &subject, format!("Output {} is still in manual override: {}", output, err)),
+    Ok(()) if !value => alerts::resolve(AlertKind::OverrideStuck, &subject),
+    _ => {}
+  }
+
+  result
+}
+
+// Handing the output back to automation isn't tied to a show, but it still
+// shouldn't keep retrying forever.
+fn release_deadline() -> DateTime<Local> {
+  Local::now() + Duration::minutes(1)
+}
+
+// Records that we put an output into manual override, so that a crashed or
+// killed scissors can hand it back to automation the next time it starts.
+// Each output gets its own marker since every channel runs on its own thread.
+const OVERRIDE_MARKER_PREFIX: &str = "scissors-override";
+
+fn override_marker_path(output: i64) -> PathBuf {
+  let mut path = dirs::document_dir().unwrap();
+  path.push(format!("{}-{}.json", OVERRIDE_MARKER_PREFIX, output));
+  path
+}
+
+// A dry run never really takes the override, so it must not leave a marker
+// behind, or clear one a real run is relying on.
+fn write_override_marker(config: &Settings, output: i64) -> Result<(), Box<dyn Error>> {
+  if !config.dry_run {
+    serde_json::to_writer(File::create(override_marker_path(output))?, &OverrideMarker { output })?;
+  }
+
+  Ok(())
+}
+
+fn clear_override_marker(config: &Settings, output: i64) -> Result<(), Box<dyn Error>> {
+  if !config.dry_run {
+    remove_file(override_marker_path(output))?;
+  }
+
+  Ok(())
+}
+
+/// Releases any automation overrides left behind by a previous run.
+pub fn release_stale_overrides(config: &Settings) -> Result<(), Box<dyn Error>> {
+  for entry in read_dir(dirs::document_dir().unwrap())? {
+    let path = entry?.path();
+    let is_marker = path.file_name()
+      .and_then(|name| name.to_str())
+      .map_or(false, |name| name.starts_with(OVERRIDE_MARKER_PREFIX) && name.ends_with(".json"));
+    if !is_marker {
+      continue;
+    }
+
+    if config.dry_run {
+      info!("Dry run, leaving stale automation override marker at {}", path.to_str().unwrap());
+      continue;
+    }
+
+    let marker: OverrideMarker = serde_json::from_reader(File::open(&path)?)?;
+    info!("Releasing stale automation override on output {}", marker.output);
+
+    set_override(config, marker.output, false, release_deadline())?;
+    remove_file(path)?;
+  }
+
+  Ok(())
+}
+
+/// Hands the output back to automation, for an override something else
+/// left on or that a crash left behind.
+pub fn release_override(config: &Settings, output: i64) -> Result<(), Box<dyn Error>> {
+  info!("Releasing automation override on output {}", output);
+  set_override(config, output, false, release_deadline())?;
+
+  if override_marker_path(output).exists() {
+    clear_override_marker(config, output)?;
+  }
+
+  Ok(())
+}
+
+/// Holds the output in manual override until it is released or dropped.
+///
+/// Dropping the guard (on error or panic) hands the output back to automation,
+/// so a failure part way through a switch can't leave it in override.
+pub struct OverrideGuard<'a> {
+  config: &'a Settings,
+  output: i64,
+  released: bool,
+}
+
+impl<'a> OverrideGuard<'a> {
+  pub fn engage(config: &'a Settings, channel: &ChannelSettings, deadline: DateTime<Local>) -> Result<Self, Box<dyn Error>> {
+    write_override_marker(config, channel.output)?;
+
+    let guard = Self { config, output: channel.output, released: false };
+    set_override(config, channel.output, true, deadline)?;
+
+    Ok(guard)
+  }
+
+  pub fn release(mut self) -> Result<(), Box<dyn Error>> {
+    set_override(self.config, self.output, false, release_deadline())?;
+    self.released = true;
+    clear_override_marker(self.config, self.output)?;
+
+    Ok(())
+  }
+}
+
+impl<'a> Drop for OverrideGuard<'a> {
+  fn drop(&mut self) {
+    if self.released {
+      return;
+    }
+
+    info!("Releasing automation override on output {}", self.output);
+    if let Err(err) = set_override(self.config, self.output, false, release_deadline()) {
+      error!("Failed to release automation override! {}", err);
+    } else if let Err(err) = clear_override_marker(self.config, self.output) {
+      error!("Failed to remove override marker! {}", err);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reels_and_media_can_leave_out_points() {
+    let summaries: EventSummaries = serde_json::from_str(r#"{
+      "eventSummaries": [],
+      "shows": [],
+      "digitalFiles": [],
+      "reels": [
+        { "id": 1, "media": 7, "cue": 15, "length": 600 },
+        { "id": 2, "media": 8 },
+        { "id": 3, "media": null, "cue": 0 }
+      ],
+      "media": [
+        { "id": 7, "name": "Meeting", "inPoint": 15, "outPoint": 615, "length": 640 },
+        { "id": 8 }
+      ]
+    }"#).unwrap();
+
+    let reels = &summaries.reels;
+    assert_eq!(reels[0].in_point(), Duration::seconds(15));
+    assert_eq!(reels[0].length(), Some(Duration::seconds(600)));
+    assert_eq!(reels[1].in_point(), Duration::zero());
+    assert_eq!(reels[1].length(), None);
+    assert_eq!(reels[2].length(), None);
+
+    let media = &summaries.media;
+    assert_eq!(media[0].in_point, Some(15));
+    assert_eq!(media[0].out_point(), Some(Duration::seconds(615)));
+    assert_eq!(media[1].out_point(), None);
+  }
+}
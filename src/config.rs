@@ -0,0 +1,1148 @@
+use std::fmt;
+use std::error::Error;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use std::collections::{HashSet, HashMap};
+use log::{info, warn, error, LevelFilter};
+use serde::{Serialize, Serializer, Deserialize};
+use serde_json::{Value, Map};
+use chrono::{DateTime, FixedOffset};
+use chrono_tz::Tz;
+use tempfile::NamedTempFile;
+
+use crate::cablecast::{self, Named};
+use crate::logging::{self, LogSettings};
+use crate::alerts::AlertSettings;
+use crate::obs;
+
+/// The config format this build writes. Older files are migrated up to it
+/// when they're loaded.
+pub const VERSION: u64 = 3;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Settings {
+  pub version: u64,
+  pub cablecast_url: String,
+  pub username: String,
+  pub password: Secret,
+  #[serde(default)]
+  pub dry_run: bool,
+  pub time_zone: Option<Tz>,
+  /// How far Cablecast's clock can be from ours before it's warned about.
+  /// Switch times are corrected for any offset over a second either way.
+  #[serde(default = "default_max_clock_skew_secs")]
+  pub max_clock_skew_secs: i64,
+  pub channels: Vec<ChannelSettings>,
+  #[serde(default)]
+  pub log: LogSettings,
+  #[serde(default)]
+  pub http: HttpSettings,
+  #[serde(default)]
+  pub alerts: AlertSettings,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HttpSettings {
+  #[serde(default = "default_http_enabled")]
+  pub enabled: bool,
+  /// Where to serve the status from. Only this PC can reach the default.
+  #[serde(default = "default_bind")]
+  pub bind: String,
+  /// Where to serve the WebSocket event feed from.
+  #[serde(default = "default_events_bind")]
+  pub events_bind: String,
+  /// What remote control requests have to send as a bearer token. Remote
+  /// control is off without one.
+  pub token: Option<Secret>,
+}
+
+fn default_http_enabled() -> bool {
+  true
+}
+
+fn default_bind() -> String {
+  "127.0.0.1:8420".to_string()
+}
+
+fn default_events_bind() -> String {
+  "127.0.0.1:8421".to_string()
+}
+
+impl Default for HttpSettings {
+  fn default() -> HttpSettings {
+    HttpSettings {
+      enabled: default_http_enabled(),
+      bind: default_bind(),
+      events_bind: default_events_bind(),
+      token: None,
+    }
+  }
+}
+
+/// A value that mustn't end up in logs. It prints and serializes as
+/// asterisks, so the real value has to be asked for with `expose`.
+#[derive(Deserialize, Clone)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+  pub fn expose(&self) -> &str {
+    &self.0
+  }
+}
+
+impl fmt::Debug for Secret {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "\"********\"")
+  }
+}
+
+impl Serialize for Secret {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str("********")
+  }
+}
+
+fn default_max_clock_skew_secs() -> i64 {
+  2
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChannelSettings {
+  pub name: Option<String>,
+  pub decklink_input: Option<String>,
+  pub decklink_output: Option<String>,
+  pub location: i64,
+  pub channel: i64,
+  pub output: i64,
+  pub device: i64,
+  pub projects: HashSet::<i64>,
+  pub templates: Option<PathBuf>,
+  pub time_zone: Option<Tz>,
+}
+
+impl ChannelSettings {
+  pub fn name(&self) -> String {
+    self.name.clone().unwrap_or_else(|| format!("channel {}", self.channel))
+  }
+
+  /// Formats a Cablecast time in the channel's time zone. Without one, the
+  /// time keeps the offset Cablecast sent it with rather than the PC's zone.
+  ///
+  /// That offset is all Cablecast reports: its times carry a UTC offset but
+  /// no zone name, so there's no IANA zone to take from it. An offset can't
+  /// tell which side of a DST change a date is on, which is what
+  /// `time_zone` is for.
+  pub fn format_time(&self, time: &DateTime<FixedOffset>, fmt: &str) -> String {
+    match self.time_zone {
+      Some(time_zone) => time.with_timezone(&time_zone).format(fmt).to_string(),
+      None => time.format(fmt).to_string(),
+    }
+  }
+
+  pub fn template_root(&self) -> PathBuf {
+    self.templates.clone().unwrap_or_else(default_template_root)
+  }
+}
+
+pub fn default_template_root() -> PathBuf {
+  let mut path = dirs::document_dir().unwrap();
+  path.push("scissors-templates");
+  path
+}
+
+// Keys that sat at the top level in version 1, back when a config described
+// exactly one channel.
+const CHANNEL_KEYS: [&str; 8] = [
+  "decklink_input",
+  "decklink_output",
+  "location",
+  "channel",
+  "output",
+  "device",
+  "projects",
+  "templates",
+];
+
+// Version 1 to 2: moves the channel keys into a one element `channels` list.
+fn nest_single_channel(config: &mut Map<String, Value>) {
+  if config.contains_key("channels") {
+    return;
+  }
+
+  let mut channel = Map::new();
+  for key in CHANNEL_KEYS.iter() {
+    if let Some(value) = config.remove(*key) {
+      channel.insert(key.to_string(), value);
+    }
+  }
+
+  config.insert("channels".to_string(), Value::Array(vec![Value::Object(channel)]));
+}
+
+// Version 2 to 3: gives `max_clock_skew` its unit.
+fn rename_max_clock_skew(config: &mut Map<String, Value>) {
+  if let Some(value) = config.remove("max_clock_skew") {
+    config.insert("max_clock_skew_secs".to_string(), value);
+  }
+}
+
+// Each entry upgrades a config from the version before it, starting at 1.
+const MIGRATIONS: [fn(&mut Map<String, Value>); 2] = [
+  nest_single_channel,
+  rename_max_clock_skew,
+];
+
+fn migrate(config: &mut Map<String, Value>) -> Result<(), Box<dyn Error>> {
+  // Version 1 configs predate the version key, and so do the first configs
+  // with a `channels` list, which are version 2 in all but name.
+  let version = match config.get("version") {
+    None if config.contains_key("channels") => 2,
+    None => 1,
+    Some(version) => version.as_u64().filter(|x| *x >= 1).ok_or_else(|| ConfigInvalid {
+      errors: vec![format!("version: must be a whole number from 1 to {}, found {}", VERSION, version)],
+    })?,
+  };
+
+  if version > VERSION {
+    return Err(Box::new(ConfigInvalid {
+      errors: vec![format!("version: {} is newer than this build of scissors understands ({})", version, VERSION)],
+    }));
+  }
+
+  if version < VERSION {
+    info!("Migrating config from version {} to {}", version, VERSION);
+    for migration in MIGRATIONS[(version - 1) as usize..].iter() {
+      migration(config);
+    }
+  }
+
+  config.insert("version".to_string(), Value::from(VERSION));
+  Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigInvalid {
+  pub errors: Vec<String>,
+}
+
+impl fmt::Display for ConfigInvalid {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "the config file has {} problem(s):", self.errors.len())?;
+    for error in &self.errors {
+      write!(f, "\n  {}", error)?;
+    }
+    Ok(())
+  }
+}
+
+impl Error for ConfigInvalid {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+  Text,
+  Flag,
+  // A config version this build understands.
+  Version,
+  Seconds,
+  Minutes,
+  // A TCP port from 1 to 65535.
+  Port,
+  // A size in bytes, at least 1.
+  Bytes,
+  // How many of something to keep, at least 1.
+  Count,
+  // A positive Cablecast id, or a name to look up.
+  Record,
+  // A non-empty list of positive Cablecast ids.
+  Records,
+  TimeZone,
+  List,
+  // Text, or where to get it from instead of the config file.
+  Secret,
+  Object,
+  Level,
+  // An IP address and port to listen on.
+  Address,
+  // A non-empty list of http:// or https:// URLs.
+  Urls,
+  Security,
+}
+
+const SETTINGS: [(&str, Kind, bool); 11] = [
+  ("version", Kind::Version, true),
+  ("cablecast_url", Kind::Text, true),
+  ("username", Kind::Secret, true),
+  ("password", Kind::Secret, true),
+  ("dry_run", Kind::Flag, false),
+  ("time_zone", Kind::TimeZone, false),
+  ("max_clock_skew_secs", Kind::Seconds, false),
+  ("channels", Kind::List, true),
+  ("log", Kind::Object, false),
+  ("http", Kind::Object, false),
+  ("alerts", Kind::Object, false),
+];
+
+const HTTP_SETTINGS: [(&str, Kind, bool); 4] = [
+  ("enabled", Kind::Flag, false),
+  ("bind", Kind::Address, false),
+  ("events_bind", Kind::Address, false),
+  ("token", Kind::Secret, false),
+];
+
+const ALERT_SETTINGS: [(&str, Kind, bool); 3] = [
+  ("webhooks", Kind::Urls, false),
+  ("email", Kind::Object, false),
+  ("cablecast_unreachable_minutes", Kind::Minutes, false),
+];
+
+const EMAIL_SETTINGS: [(&str, Kind, bool); 7] = [
+  ("server", Kind::Text, true),
+  ("port", Kind::Port, false),
+  ("security", Kind::Security, false),
+  ("username", Kind::Text, false),
+  ("password", Kind::Secret, false),
+  ("from", Kind::Text, true),
+  ("to", Kind::List, true),
+];
+
+const LOG_SETTINGS: [(&str, Kind, bool); 4] = [
+  ("directory", Kind::Text, false),
+  ("max_file_size", Kind::Bytes, false),
+  ("keep_files", Kind::Count, false),
+  ("levels", Kind::Object, false),
+];
+
+const CHANNEL_SETTINGS: [(&str, Kind, bool); 10] = [
+  ("name", Kind::Text, false),
+  ("decklink_input", Kind::Text, false),
+  ("decklink_output", Kind::Text, false),
+  ("location", Kind::Record, true),
+  ("channel", Kind::Record, true),
+  ("output", Kind::Record, true),
+  ("device", Kind::Record, true),
+  ("projects", Kind::Records, true),
+  ("templates", Kind::Text, false),
+  ("time_zone", Kind::TimeZone, false),
+];
+
+fn is_id(value: &Value) -> bool {
+  value.as_i64().map_or(false, |x| x > 0)
+}
+
+fn check_value(at: &str, value: &Value, kind: Kind) -> Option<String> {
+  let ok = match kind {
+    Kind::Text => value.as_str().map_or(false, |x| !x.trim().is_empty()),
+    Kind::Flag => value.is_boolean(),
+    Kind::Version => value.as_u64().map_or(false, |x| (1..=VERSION).contains(&x)),
+    Kind::Seconds | Kind::Minutes => value.as_u64().is_some(),
+    Kind::Port => value.as_u64().map_or(false, |x| (1..=u64::from(u16::MAX)).contains(&x)),
+    Kind::Bytes | Kind::Count => value.as_u64().map_or(false, |x| x >= 1),
+    Kind::Record => is_id(value) || value.as_str().map_or(false, |x| !x.trim().is_empty()),
+    Kind::Records => value.as_array().map_or(false, |x| !x.is_empty() && x.iter().all(is_id)),
+    Kind::TimeZone => value.as_str().map_or(false, |x| x.parse::<Tz>().is_ok()),
+    Kind::List => value.as_array().map_or(false, |x| !x.is_empty()),
+    Kind::Secret => value.as_str().map_or(false, |x| !x.trim().is_empty()) || secret_source(value).is_some(),
+    Kind::Object => value.is_object(),
+    Kind::Level => value.as_str().map_or(false, |x| x.parse::<LevelFilter>().is_ok()),
+    Kind::Address => value.as_str().map_or(false, |x| x.parse::<SocketAddr>().is_ok()),
+    Kind::Urls => value.as_array().map_or(false, |x| {
+      !x.is_empty() && x.iter().all(|x| x.as_str().map_or(false, |x| x.starts_with("http://") || x.starts_with("https://")))
+    }),
+    Kind::Security => value.as_str().map_or(false, |x| ["none", "starttls", "tls"].contains(&x)),
+  };
+
+  if ok {
+    return None;
+  }
+
+  let expected = match kind {
+    Kind::Text => "non-empty text",
+    Kind::Flag => "true or false",
+    Kind::Version => "a version from 1 to this build's",
+    Kind::Seconds => "a whole number of zero or more",
+    Kind::Minutes => "a whole number of minutes, zero or more",
+    Kind::Port => "a port from 1 to 65535",
+    Kind::Bytes => "a whole number of bytes, 1 or more",
+    Kind::Count => "a whole number of 1 or more",
+    Kind::Record => "a positive id or a name",
+    Kind::Records => "a non-empty list of positive ids",
+    Kind::TimeZone => "an IANA time zone such as \"America/Chicago\"",
+    Kind::List => "a non-empty list",
+    Kind::Secret => "non-empty text, or one of {\"env\": ...}, {\"file\": ...} or {\"command\": ...}",
+    Kind::Object => "an object",
+    Kind::Level => "one of off, error, warn, info, debug or trace",
+    Kind::Address => "an address and port such as \"127.0.0.1:8420\"",
+    Kind::Urls => "a non-empty list of http:// or https:// URLs",
+    Kind::Security => "one of none, starttls or tls",
+  };
+
+  Some(format!("{}: must be {}, found {}", at, expected, value))
+}
+
+fn check_object(at: &str, object: &Map<String, Value>, settings: &[(&str, Kind, bool)], errors: &mut Vec<String>) {
+  let prefix = if at.is_empty() { String::new() } else { format!("{}.", at) };
+
+  for (key, kind, required) in settings {
+    match object.get(*key) {
+      None | Some(Value::Null) if *required => errors.push(format!("{}{}: is required", prefix, key)),
+      None | Some(Value::Null) => {}
+      Some(value) => errors.extend(check_value(&format!("{}{}", prefix, key), value, *kind)),
+    }
+  }
+
+  for key in object.keys() {
+    if !settings.iter().any(|(known, _, _)| known == key) {
+      errors.push(format!("{}{}: isn't a setting scissors knows about", prefix, key));
+    }
+  }
+}
+
+// Checks every setting's type and range before serde sees them, so a config
+// with several mistakes reports all of them rather than just the first.
+fn check(config: &Map<String, Value>) -> Result<(), ConfigInvalid> {
+  let mut errors = Vec::new();
+  check_object("", config, &SETTINGS, &mut errors);
+
+  if let Some(url) = config.get("cablecast_url").and_then(Value::as_str) {
+    if url.contains("://") || url.ends_with('/') {
+      errors.push(format!("cablecast_url: must be just the host name, such as \"cablecast.example.org\", found \"{}\"", url));
+    }
+  }
+
+  if let Some(channels) = config.get("channels").and_then(Value::as_array) {
+    for (i, channel) in channels.iter().enumerate() {
+      let at = format!("channels[{}]", i);
+      match channel.as_object() {
+        Some(channel) => check_object(&at, channel, &CHANNEL_SETTINGS, &mut errors),
+        None => errors.push(format!("{}: must be an object, found {}", at, channel)),
+      }
+    }
+  }
+
+  if let Some(log) = config.get("log").and_then(Value::as_object) {
+    check_object("log", log, &LOG_SETTINGS, &mut errors);
+
+    let levels: Vec<(&str, Kind, bool)> = logging::MODULES.iter().map(|(module, _)| (*module, Kind::Level, false)).collect();
+    if let Some(log_levels) = log.get("levels").and_then(Value::as_object) {
+      check_object("log.levels", log_levels, &levels, &mut errors);
+    }
+  }
+
+  if let Some(http) = config.get("http").and_then(Value::as_object) {
+    check_object("http", http, &HTTP_SETTINGS, &mut errors);
+  }
+
+  if let Some(alerts) = config.get("alerts").and_then(Value::as_object) {
+    check_object("alerts", alerts, &ALERT_SETTINGS, &mut errors);
+
+    if let Some(email) = alerts.get("email").and_then(Value::as_object) {
+      check_object("alerts.email", email, &EMAIL_SETTINGS, &mut errors);
+    }
+  }
+
+  if errors.is_empty() {
+    Ok(())
+  } else {
+    Err(ConfigInvalid { errors })
+  }
+}
+
+// Where a secret setting says to read its value from, when it isn't given
+// inline.
+fn secret_source(value: &Value) -> Option<(&str, &str)> {
+  let object = value.as_object()?;
+  if object.len() != 1 {
+    return None;
+  }
+
+  let (source, from) = object.iter().next()?;
+  match source.as_str() {
+    "env" | "file" | "command" => Some((source, from.as_str().filter(|x| !x.trim().is_empty())?)),
+    _ => None,
+  }
+}
+
+// Anyone else being able to read the secrets file defeats the point of
+// moving secrets out of the config.
+#[cfg(unix)]
+fn check_private(path: &str) -> Result<(), String> {
+  use std::os::unix::fs::PermissionsExt;
+
+  let mode = std::fs::metadata(path).map_err(|err| err.to_string())?.permissions().mode();
+  if mode & 0o077 != 0 {
+    return Err(format!("{} can be read by other users, run `chmod 600 {}`", path, path));
+  }
+
+  Ok(())
+}
+
+// On Windows that means no allow entry in the file's DACL letting Everyone,
+// Authenticated Users or Users read it, which between them take in every
+// account on the PC.
+#[cfg(windows)]
+fn check_private(path: &str) -> Result<(), String> {
+  use std::ffi::OsStr;
+  use std::os::windows::ffi::OsStrExt;
+  use std::ptr::null_mut;
+  use winapi::shared::winerror::ERROR_SUCCESS;
+  use winapi::um::accctrl::SE_FILE_OBJECT;
+  use winapi::um::aclapi::GetNamedSecurityInfoW;
+  use winapi::um::securitybaseapi::{CreateWellKnownSid, EqualSid, GetAce};
+  use winapi::um::winbase::LocalFree;
+  use winapi::um::winnt::{
+    ACCESS_ALLOWED_ACE, ACCESS_ALLOWED_ACE_TYPE, DACL_SECURITY_INFORMATION, FILE_READ_DATA, GENERIC_ALL, GENERIC_READ,
+    PACL, PSECURITY_DESCRIPTOR, SECURITY_MAX_SID_SIZE, WinAuthenticatedUserSid, WinBuiltinUsersSid, WinWorldSid,
+  };
+
+  let mut everyone = Vec::new();
+  for kind in [WinWorldSid, WinAuthenticatedUserSid, WinBuiltinUsersSid].iter() {
+    let mut sid = vec![0u8; SECURITY_MAX_SID_SIZE];
+    let mut size = sid.len() as u32;
+    if unsafe { CreateWellKnownSid(*kind, null_mut(), sid.as_mut_ptr() as _, &mut size) } != 0 {
+      everyone.push(sid);
+    }
+  }
+
+  let name: Vec<u16> = OsStr::new(path).encode_wide().chain(Some(0)).collect();
+  let mut dacl: PACL = null_mut();
+  let mut descriptor: PSECURITY_DESCRIPTOR = null_mut();
+  let status = unsafe {
+    GetNamedSecurityInfoW(name.as_ptr(), SE_FILE_OBJECT, DACL_SECURITY_INFORMATION, null_mut(), null_mut(), &mut dacl, null_mut(), &mut descriptor)
+  };
+  if status != ERROR_SUCCESS {
+    return Err(format!("couldn't read the permissions on {}, error {}", path, status));
+  }
+
+  // A file without a DACL at all is open to everyone.
+  let mut readable = dacl.is_null();
+  let count = if dacl.is_null() { 0 } else { unsafe { (*dacl).AceCount } };
+  for i in 0..count {
+    let mut ace = null_mut();
+    if unsafe { GetAce(dacl, i as u32, &mut ace) } == 0 {
+      continue;
+    }
+
+    let ace = unsafe { &*(ace as *const ACCESS_ALLOWED_ACE) };
+    if ace.Header.AceType != ACCESS_ALLOWED_ACE_TYPE || ace.Mask & (FILE_READ_DATA | GENERIC_READ | GENERIC_ALL) == 0 {
+      continue;
+    }
+
+    let sid = &ace.SidStart as *const u32 as *mut _;
+    if everyone.iter_mut().any(|x| unsafe { EqualSid(sid, x.as_mut_ptr() as _) } != 0) {
+      readable = true;
+    }
+  }
+
+  unsafe {
+    LocalFree(descriptor);
+  }
+
+  if readable {
+    return Err(format!("{} can be read by other users, run `icacls \"{}\" /inheritance:r /grant:r %USERNAME%:F`", path, path));
+  }
+
+  Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn check_private(_path: &str) -> Result<(), String> {
+  Ok(())
+}
+
+fn read_secret(source: &str, from: &str) -> Result<String, String> {
+  let secret = match source {
+    "env" => std::env::var(from).map_err(|_| format!("environment variable {} isn't set", from))?,
+    "file" => {
+      check_private(from)?;
+      std::fs::read_to_string(from).map_err(|err| format!("couldn't read {}: {}", from, err))?
+    }
+    _ => {
+      let output = if cfg!(windows) {
+        Command::new("cmd").args(&["/C", from]).output()
+      } else {
+        Command::new("sh").args(&["-c", from]).output()
+      };
+      let output = output.map_err(|err| format!("couldn't run `{}`: {}", from, err))?;
+      if !output.status.success() {
+        return Err(format!("`{}` failed with {}", from, output.status));
+      }
+
+      String::from_utf8(output.stdout).map_err(|_| format!("`{}` didn't print UTF-8", from))?
+    }
+  };
+
+  // Files and commands almost always end with a newline that isn't part of
+  // the secret.
+  let secret = secret.trim_end_matches(|x| x == '\r' || x == '\n').to_string();
+  if secret.is_empty() {
+    return Err(format!("{} {} is empty", source, from));
+  }
+
+  Ok(secret)
+}
+
+fn resolve_secret(object: &mut Map<String, Value>, key: &str, path: &str, errors: &mut Vec<String>) {
+  let (source, from) = match object.get(key).and_then(secret_source) {
+    Some((source, from)) => (source.to_string(), from.to_string()),
+    None => return,
+  };
+
+  match read_secret(&source, &from) {
+    Ok(secret) => {
+      object.insert(key.to_string(), Value::from(secret));
+    }
+    Err(err) => errors.push(format!("{}: {}", path, err)),
+  }
+}
+
+// Replaces secrets given as an env var, file or command with their values.
+fn resolve_secrets(config: &mut Map<String, Value>) -> Result<(), ConfigInvalid> {
+  let mut errors = Vec::new();
+
+  for key in ["username", "password"].iter() {
+    resolve_secret(config, key, key, &mut errors);
+  }
+
+  if let Some(http) = config.get_mut("http").and_then(Value::as_object_mut) {
+    resolve_secret(http, "token", "http.token", &mut errors);
+  }
+
+  let email = config.get_mut("alerts").and_then(Value::as_object_mut)
+    .and_then(|x| x.get_mut("email")).and_then(Value::as_object_mut);
+  if let Some(email) = email {
+    resolve_secret(email, "password", "alerts.email.password", &mut errors);
+  }
+
+  if errors.is_empty() {
+    Ok(())
+  } else {
+    Err(ConfigInvalid { errors })
+  }
+}
+
+// Checks that need names resolved to ids first.
+fn check_channels(config: &Settings) -> Result<(), ConfigInvalid> {
+  let mut errors = Vec::new();
+
+  // Each channel mixes its audio on a track of its own.
+  if config.channels.len() > obs::MAX_AUDIO_MIXES as usize {
+    errors.push(format!("channels: at most {} channels fit, found {}", obs::MAX_AUDIO_MIXES, config.channels.len()));
+  }
+
+  for (i, channel) in config.channels.iter().enumerate() {
+    // Left out, every channel would open the same default DeckLink ports.
+    if config.channels.len() > 1 {
+      if channel.decklink_input.is_none() {
+        errors.push(format!("channels[{}].decklink_input: is required when there's more than one channel", i));
+      }
+
+      if channel.decklink_output.is_none() {
+        errors.push(format!("channels[{}].decklink_output: is required when there's more than one channel", i));
+      }
+    }
+
+    for (j, other) in config.channels.iter().enumerate().take(i) {
+      if channel.output == other.output {
+        errors.push(format!("channels[{}].output: output {} is already used by channels[{}]", i, channel.output, j));
+      }
+
+      if channel.name() == other.name() {
+        errors.push(format!("channels[{}].name: \"{}\" is already used by channels[{}]", i, channel.name(), j));
+      }
+
+      if channel.decklink_input.is_some() && channel.decklink_input == other.decklink_input {
+        errors.push(format!("channels[{}].decklink_input: already used by channels[{}]", i, j));
+      }
+
+      if channel.decklink_output.is_some() && channel.decklink_output == other.decklink_output {
+        errors.push(format!("channels[{}].decklink_output: already used by channels[{}]", i, j));
+      }
+    }
+  }
+
+  if errors.is_empty() {
+    Ok(())
+  } else {
+    Err(ConfigInvalid { errors })
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct NameNotFound {
+  pub kind: String,
+  pub name: String,
+}
+
+impl fmt::Display for NameNotFound {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "no {} named \"{}\" was found in Cablecast", self.kind, self.name)
+  }
+}
+
+impl Error for NameNotFound {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct NameAmbiguous {
+  pub kind: String,
+  pub name: String,
+  pub ids: Vec<i64>,
+}
+
+impl fmt::Display for NameAmbiguous {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "more than one {} is named \"{}\" (ids {:?}), use the id instead", self.kind, self.name, self.ids)
+  }
+}
+
+impl Error for NameAmbiguous {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigMissing {
+  pub path: PathBuf,
+}
+
+impl fmt::Display for ConfigMissing {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "could not find config file {}, run `scissors init` to create one", self.path.display())
+  }
+}
+
+impl Error for ConfigMissing {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
+
+#[derive(Debug, Deserialize)]
+struct Server {
+  cablecast_url: String,
+  username: String,
+  password: String,
+}
+
+// Channel keys that can name a Cablecast record instead of giving its id,
+// with the API collection the name is looked up in. The location comes first
+// so it can tell apart outputs and devices that share a name.
+const NAMED_KEYS: [(&str, &str); 4] = [
+  ("location", "locations"),
+  ("channel", "channels"),
+  ("output", "outputs"),
+  ("device", "devices"),
+];
+
+fn find_named(records: &[Named], kind: &str, name: &str, location: Option<i64>) -> Result<i64, Box<dyn Error>> {
+  let mut matches: Vec<&Named> = records.iter()
+    .filter(|x| x.name.trim().eq_ignore_ascii_case(name.trim()))
+    .collect();
+
+  if matches.len() > 1 && location.is_some() {
+    matches.retain(|x| x.location.is_none() || x.location == location);
+  }
+
+  match matches.as_slice() {
+    [] => Err(Box::new(NameNotFound { kind: kind.to_string(), name: name.to_string() })),
+    [record] => Ok(record.id),
+    _ => Err(Box::new(NameAmbiguous {
+      kind: kind.to_string(),
+      name: name.to_string(),
+      ids: matches.iter().map(|x| x.id).collect(),
+    })),
+  }
+}
+
+fn names_cache_path() -> PathBuf {
+  let mut path = dirs::document_dir().unwrap();
+  path.push("scissors-names-cache.json");
+  path
+}
+
+// The ids names last resolved to, keyed by `names_cache_key`.
+fn load_names_cache() -> HashMap<String, i64> {
+  File::open(names_cache_path()).ok()
+    .and_then(|file| serde_json::from_reader(file).ok())
+    .unwrap_or_default()
+}
+
+// Written through a temporary file, the same as the schedule cache.
+fn save_names_cache(cache: &HashMap<String, i64>) -> Result<(), Box<dyn Error>> {
+  let path = names_cache_path();
+  let file = NamedTempFile::new_in(path.parent().unwrap())?;
+  serde_json::to_writer(&file, cache)?;
+  file.persist(path)?;
+
+  Ok(())
+}
+
+// The location is part of the key, since it can change what a name resolves
+// to.
+fn names_cache_key(key: &str, name: &str, location: Option<i64>) -> String {
+  match location {
+    Some(location) => format!("{}/{}/{}", location, key, name.trim().to_lowercase()),
+    None => format!("{}/{}", key, name.trim().to_lowercase()),
+  }
+}
+
+// Replaces any names given for a channel's location, channel, output or
+// device with their ids, looking them up in Cablecast. When Cablecast can't be
+// reached, the ids they resolved to last time are used instead.
+fn resolve_names(config: &mut Map<String, Value>) -> Result<(), Box<dyn Error>> {
+  let channels = match config.get("channels") {
+    Some(Value::Array(channels)) => channels,
+    _ => return Ok(()),
+  };
+
+  let has_names = channels.iter().any(|channel| {
+    NAMED_KEYS.iter().any(|(key, _)| channel.get(key).map_or(false, Value::is_string))
+  });
+  if !has_names {
+    return Ok(());
+  }
+
+  let server: Server = serde_json::from_value(Value::Object(config.clone()))?;
+  // `None` for the kinds Cablecast couldn't list.
+  let mut records: HashMap<&str, Option<Vec<Named>>> = HashMap::new();
+  let mut cache = load_names_cache();
+
+  if let Some(Value::Array(channels)) = config.get_mut("channels") {
+    for channel in channels.iter_mut().filter_map(Value::as_object_mut) {
+      let mut location = channel.get("location").and_then(Value::as_i64);
+
+      for (key, kind) in NAMED_KEYS.iter() {
+        let name = match channel.get(*key) {
+          Some(Value::String(name)) => name.clone(),
+          _ => continue,
+        };
+
+        let listed = records.entry(kind).or_insert_with(|| {
+          match cablecast::named(&server.cablecast_url, &server.username, &server.password, kind) {
+            Ok(listed) => Some(listed),
+            Err(err) => {
+              warn!("Failed to look up {} in Cablecast! {}", kind, err);
+              None
+            }
+          }
+        });
+
+        let cache_key = names_cache_key(key, &name, location);
+        let id = match listed {
+          Some(listed) => {
+            let id = find_named(listed, key, &name, location)?;
+            info!("Resolved {} \"{}\" to id {}", key, name, id);
+            id
+          }
+          None => match cache.get(&cache_key) {
+            Some(id) => {
+              warn!("Using id {} for {} \"{}\", from when Cablecast last resolved it", id, key, name);
+              *id
+            }
+            None => return Err(format!("couldn't look up {} \"{}\" in Cablecast, and it has never been resolved before", key, name).into()),
+          },
+        };
+
+        cache.insert(cache_key, id);
+        channel.insert(key.to_string(), Value::from(id));
+        if *key == "location" {
+          location = Some(id);
+        }
+      }
+    }
+  }
+
+  if let Err(err) = save_names_cache(&cache) {
+    warn!("Failed to save names cache! {}", err);
+  }
+
+  Ok(())
+}
+
+pub fn default_path() -> PathBuf {
+  let mut path = dirs::document_dir().unwrap();
+  path.push("scissors-config.json");
+  path
+}
+
+/// Builds settings from a config file's JSON, upgrading older layouts and
+/// looking up any names given in place of ids.
+pub fn parse(mut value: Value) -> Result<Settings, Box<dyn Error>> {
+  let object = value.as_object_mut().ok_or_else(|| ConfigInvalid {
+    errors: vec!["the config must be a JSON object".to_string()],
+  })?;
+
+  migrate(object)?;
+  check(object)?;
+  resolve_secrets(object)?;
+  resolve_names(object)?;
+
+  let mut config: Settings = serde_json::from_value(value)?;
+  check_channels(&config)?;
+
+  for channel in &mut config.channels {
+    if channel.time_zone.is_none() {
+      channel.time_zone = config.time_zone;
+    }
+  }
+
+  Ok(config)
+}
+
+/// The config as scissors will run it, with defaults filled in, names
+/// resolved and the password hidden.
+pub fn effective(config: &Settings) -> Result<String, Box<dyn Error>> {
+  Ok(serde_json::to_string_pretty(config)?)
+}
+
+pub fn load(path: &Path) -> Result<Settings, Box<dyn Error>> {
+  if !path.exists() {
+    return Err(Box::new(ConfigMissing { path: path.to_path_buf() }));
+  }
+
+  parse(serde_json::from_reader(File::open(path)?)?)
+}
+
+// How often to check the config file for changes.
+const RELOAD_INTERVAL_SECS: u64 = 5;
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+  std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+/// The running config, replaced whenever the config file changes. Channels
+/// pick up the new settings between shows, never partway through one.
+#[derive(Clone)]
+pub struct Live {
+  settings: Arc<RwLock<Settings>>,
+  path: PathBuf,
+  // Set by `--dry-run`, which has to survive a reload of a file without it.
+  dry_run: bool,
+}
+
+impl Live {
+  pub fn new(mut settings: Settings, path: PathBuf, dry_run: bool) -> Live {
+    settings.dry_run |= dry_run;
+    Live {
+      settings: Arc::new(RwLock::new(settings)),
+      path,
+      dry_run,
+    }
+  }
+
+  pub fn settings(&self) -> Settings {
+    self.settings.read().unwrap().clone()
+  }
+
+  /// The current settings, along with the channel that drives `output`.
+  pub fn channel(&self, output: i64) -> Option<(Settings, ChannelSettings)> {
+    let settings = self.settings();
+    let channel = settings.channels.iter().find(|x| x.output == output)?.clone();
+    Some((settings, channel))
+  }
+
+  // Channels are told apart by their output, since no two can share one.
+  fn apply(&self, mut settings: Settings) {
+    settings.dry_run |= self.dry_run;
+
+    let mut current = self.settings.write().unwrap();
+    for channel in &settings.channels {
+      match current.channels.iter().find(|x| x.output == channel.output) {
+        None => warn!("Config reload: new channel {} for output {} won't start until scissors is restarted", channel.name(), channel.output),
+        Some(old) if old.decklink_input != channel.decklink_input || old.decklink_output != channel.decklink_output => {
+          warn!("Config reload: DeckLink devices for {} changed, I/O will be set up again before the next show", channel.name());
+        }
+        Some(_) => {}
+      }
+    }
+
+    for channel in &current.channels {
+      if !settings.channels.iter().any(|x| x.output == channel.output) {
+        warn!("Config reload: {} was removed but keeps running until scissors is restarted", channel.name());
+      }
+    }
+
+    if settings.http.enabled != current.http.enabled || settings.http.bind != current.http.bind || settings.http.events_bind != current.http.events_bind {
+      warn!("Config reload: HTTP server changes won't apply until scissors is restarted");
+    }
+
+    if let Err(err) = logging::configure(&settings.log, false) {
+      error!("Failed to apply the new log levels! {}", err);
+    }
+
+    *current = settings;
+    info!("Config reloaded");
+  }
+
+  /// Checks the config file for changes in the background, reloading it
+  /// whenever it changes. A file that doesn't load leaves the running config
+  /// as it was.
+  pub fn watch(&self) {
+    let live = self.clone();
+    std::thread::spawn(move || {
+      let mut modified = modified_at(&live.path);
+      loop {
+        std::thread::sleep(std::time::Duration::from_secs(RELOAD_INTERVAL_SECS));
+
+        let now = modified_at(&live.path);
+        if now == modified {
+          continue;
+        }
+        modified = now;
+
+        let settings = File::open(&live.path)
+          .map_err(|err| Box::new(err) as Box<dyn Error>)
+          .and_then(|file| parse(serde_json::from_reader(file)?));
+        match settings {
+          Ok(settings) => live.apply(settings),
+          Err(err) => error!("Not reloading the config! {}", err),
+        }
+      }
+    });
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+
+  pub(crate) fn time(rfc3339: &str) -> DateTime<FixedOffset> {
+    DateTime::parse_from_rfc3339(rfc3339).unwrap()
+  }
+
+  pub(crate) fn channel(time_zone: Option<Tz>) -> ChannelSettings {
+    ChannelSettings {
+      name: None,
+      decklink_input: None,
+      decklink_output: None,
+      location: 1,
+      channel: 2,
+      output: 3,
+      device: 4,
+      projects: HashSet::new(),
+      templates: None,
+      time_zone,
+    }
+  }
+
+  fn migrated(mut config: Value) -> Value {
+    migrate(config.as_object_mut().unwrap()).unwrap();
+    config
+  }
+
+  #[test]
+  fn migrate_nests_a_version_1_channel() {
+    let config = migrated(serde_json::json!({ "cablecast_url": "cablecast", "output": 3, "device": 4 }));
+    assert_eq!(config, serde_json::json!({
+      "version": VERSION,
+      "cablecast_url": "cablecast",
+      "channels": [{ "output": 3, "device": 4 }],
+    }));
+  }
+
+  #[test]
+  fn migrate_keeps_unversioned_channels() {
+    let channels = serde_json::json!([{ "output": 3, "device": 4 }, { "output": 5, "device": 6 }]);
+    let config = migrated(serde_json::json!({ "channels": channels.clone(), "max_clock_skew": 5 }));
+    assert_eq!(config, serde_json::json!({ "version": VERSION, "channels": channels, "max_clock_skew_secs": 5 }));
+  }
+
+  #[test]
+  fn migrate_refuses_newer_versions() {
+    let mut config = serde_json::json!({ "version": VERSION + 1, "channels": [] });
+    assert!(migrate(config.as_object_mut().unwrap()).is_err());
+  }
+
+  #[test]
+  fn check_wants_real_ports() {
+    let config = serde_json::json!({ "alerts": { "email": { "server": "mail", "port": 70000, "from": "a@b", "to": ["c@d"] } } });
+    let errors = check(config.as_object().unwrap()).unwrap_err().errors;
+    assert!(errors.contains(&"alerts.email.port: must be a port from 1 to 65535, found 70000".to_string()), "{:?}", errors);
+  }
+
+  #[test]
+  fn check_channels_wants_decklink_devices_for_each_of_several_channels() {
+    let config: Settings = serde_json::from_value(serde_json::json!({
+      "version": VERSION,
+      "cablecast_url": "cablecast",
+      "username": "admin",
+      "password": "secret",
+      "channels": [
+        { "location": 1, "channel": 2, "output": 3, "device": 4, "projects": [], "decklink_input": "a", "decklink_output": "b" },
+        { "location": 1, "channel": 5, "output": 6, "device": 7, "projects": [], "decklink_input": "c" },
+      ],
+    })).unwrap();
+    let errors = check_channels(&config).unwrap_err().errors;
+    assert_eq!(errors, vec!["channels[1].decklink_output: is required when there's more than one channel".to_string()]);
+  }
+
+  #[test]
+  fn load_points_a_missing_file_at_init() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("scissors-config.json");
+    let err = load(&path).unwrap_err();
+    assert_eq!(err.to_string(), format!("could not find config file {}, run `scissors init` to create one", path.display()));
+  }
+
+  #[test]
+  fn check_wants_log_files_to_hold_something() {
+    let config = serde_json::json!({ "log": { "max_file_size": 0, "keep_files": 0 } });
+    let errors = check(config.as_object().unwrap()).unwrap_err().errors;
+    assert!(errors.contains(&"log.max_file_size: must be a whole number of bytes, 1 or more, found 0".to_string()), "{:?}", errors);
+    assert!(errors.contains(&"log.keep_files: must be a whole number of 1 or more, found 0".to_string()), "{:?}", errors);
+  }
+
+  const FORMAT: &str = "%Y-%m-%d %H:%M %Z";
+
+  #[test]
+  fn format_time_springs_forward() {
+    // 2 AM Central on March 8, 2020 went straight to 3 AM.
+    let channel = channel(Some(chrono_tz::America::Chicago));
+    assert_eq!(channel.format_time(&time("2020-03-08T01:59:00-06:00"), FORMAT), "2020-03-08 01:59 CST");
+    assert_eq!(channel.format_time(&time("2020-03-08T02:00:00-06:00"), FORMAT), "2020-03-08 03:00 CDT");
+    assert_eq!(channel.format_time(&time("2020-03-08T08:00:00Z"), FORMAT), "2020-03-08 03:00 CDT");
+  }
+
+  #[test]
+  fn format_time_falls_back() {
+    // 1 AM to 2 AM Central happened twice on November 1, 2020.
+    let channel = channel(Some(chrono_tz::America::Chicago));
+    assert_eq!(channel.format_time(&time("2020-11-01T01:30:00-05:00"), FORMAT), "2020-11-01 01:30 CDT");
+    assert_eq!(channel.format_time(&time("2020-11-01T01:30:00-06:00"), FORMAT), "2020-11-01 01:30 CST");
+    assert_eq!(channel.format_time(&time("2020-11-01T02:30:00-05:00"), FORMAT), "2020-11-01 01:30 CST");
+  }
+
+  #[test]
+  fn format_time_uses_the_zone_over_a_stale_offset() {
+    // Sent with the offset from before the change, as a server a zone behind
+    // would.
+    let channel = channel(Some(chrono_tz::America::Chicago));
+    assert_eq!(channel.format_time(&time("2020-03-08T12:00:00-06:00"), FORMAT), "2020-03-08 13:00 CDT");
+  }
+
+  #[test]
+  fn format_time_keeps_the_date_of_a_late_show() {
+    // 11:30 PM in Chicago is already the next day in UTC.
+    let channel = channel(Some(chrono_tz::America::Chicago));
+    assert_eq!(channel.format_time(&time("2020-11-02T05:30:00Z"), "%B %d, %Y"), "November 01, 2020");
+  }
+
+  #[test]
+  fn format_time_without_a_zone_keeps_the_offset() {
+    let channel = channel(None);
+    assert_eq!(channel.format_time(&time("2020-03-08T12:00:00-06:00"), "%Y-%m-%d %H:%M %:z"), "2020-03-08 12:00 -06:00");
+  }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Cursor;
+use std::sync::{Mutex, Condvar};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, FixedOffset, Local};
+use lazy_static::lazy_static;
+use log::info;
+use serde::{Serialize, Deserialize};
+use serde_json::json;
+use tiny_http::{Request as HttpRequest, Response, Method};
+
+use crate::config::{ChannelSettings, Live};
+use crate::schedule::Event;
+use crate::status;
+use crate::template::{self, Template, Decision};
+
+/// Something master control can ask a channel to do.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+  /// Leave shows on the fallback scene, without switching outputs, until
+  /// turned off.
+  ForceFallback { on: bool },
+  /// Frame the show on air, or the next one, with a template other than its
+  /// own. No template goes back to the show's own.
+  ForceTemplate { template: Option<String> },
+  /// Leave the next show alone.
+  SkipNext,
+  /// Keep whatever scene is up, arming nothing, until turned off.
+  Hold { on: bool },
+  SetupIo,
+  ReleaseOverride,
+  /// Put the show on air back up from its template files.
+  ReloadTemplates,
+}
+
+impl fmt::Display for Action {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let on_off = |on: &bool| if *on { "on" } else { "off" };
+    match self {
+      Action::ForceFallback { on } => write!(f, "force fallback {}", on_off(on)),
+      Action::ForceTemplate { template: Some(template) } => write!(f, "force template {}", template),
+      Action::ForceTemplate { template: None } => write!(f, "stop forcing a template"),
+      Action::SkipNext => write!(f, "skip the next show"),
+      Action::Hold { on } => write!(f, "hold {}", on_off(on)),
+      Action::SetupIo => write!(f, "set up I/O again"),
+      Action::ReleaseOverride => write!(f, "release the automation override"),
+      Action::ReloadTemplates => write!(f, "reload templates"),
+    }
+  }
+}
+
+/// An action waiting for its channel's scheduler to get to it.
+#[derive(Debug, Serialize, Clone)]
+pub struct Request {
+  pub action: Action,
+  pub at: DateTime<Local>,
+  pub from: String,
+}
+
+impl fmt::Display for Request {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} (from {} at {})", self.action, self.from, self.at.to_rfc3339())
+  }
+}
+
+/// An event on a channel, told apart from reruns of the same show by when
+/// it starts.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub struct ShowKey {
+  pub show: i64,
+  pub start: DateTime<FixedOffset>,
+}
+
+impl ShowKey {
+  pub fn of(event: &Event) -> ShowKey {
+    ShowKey { show: event.show.id, start: event.summary.start }
+  }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ForcedTemplate {
+  pub event: ShowKey,
+  pub template: String,
+}
+
+/// What operators have asked of a channel that's still in effect.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct Controls {
+  pub force_fallback: bool,
+  pub hold: bool,
+  pub template: Option<ForcedTemplate>,
+  pub skip: Option<ShowKey>,
+}
+
+impl Controls {
+  /// Decides how to play out the event, as `template::decide` would unless
+  /// an operator said otherwise.
+  pub fn decide(&self, channel: &ChannelSettings, event: &Event, force_fallback: bool) -> Decision {
+    let key = ShowKey::of(event);
+    if self.skip == Some(key) {
+      return Decision::Untouched { reason: "the operator skipped it".to_string() };
+    }
+
+    if self.force_fallback {
+      return Decision::Untouched { reason: "the operator is forcing fallback".to_string() };
+    }
+
+    if !force_fallback {
+      let forced = self.template.as_ref().filter(|x| x.event == key);
+      if let Some(template) = forced.and_then(|x| Template::named(&channel.template_root(), &x.template)) {
+        return template::check(template);
+      }
+    }
+
+    template::decide(channel, event, force_fallback)
+  }
+
+  /// Forgets skips and forced templates for events before `armed`.
+  pub fn expire(&mut self, armed: &ShowKey) {
+    if self.skip.map_or(false, |x| x.start < armed.start) {
+      self.skip = None;
+    }
+
+    if self.template.as_ref().map_or(false, |x| x.event.start < armed.start) {
+      self.template = None;
+    }
+  }
+}
+
+lazy_static! {
+  static ref QUEUE: Mutex<HashMap<i64, Vec<Request>>> = Mutex::new(HashMap::new());
+  static ref ARRIVED: Condvar = Condvar::new();
+}
+
+/// Queues a request for the channel on `output`, waking it if it's waiting.
+pub fn submit(output: i64, request: Request) {
+  QUEUE.lock().unwrap().entry(output).or_insert_with(Vec::new).push(request);
+  ARRIVED.notify_all();
+}
+
+/// Everything queued for the channel on `output`, oldest first.
+pub fn take(output: i64) -> Vec<Request> {
+  QUEUE.lock().unwrap().remove(&output).unwrap_or_default()
+}
+
+/// Sleeps for `duration`, or until there's a request for the channel on
+/// `output`. Returns whether there is one.
+pub fn wait(output: i64, duration: Duration) -> bool {
+  let deadline = Instant::now() + duration;
+  let mut queue = QUEUE.lock().unwrap();
+  loop {
+    if queue.get(&output).map_or(false, |x| !x.is_empty()) {
+      return true;
+    }
+
+    let now = Instant::now();
+    if now >= deadline {
+      return false;
+    }
+
+    queue = ARRIVED.wait_timeout(queue, deadline - now).unwrap().0;
+  }
+}
+
+// Compares every byte so how long a wrong token takes doesn't give away how
+// much of it was right.
+fn same_token(given: &str, token: &str) -> bool {
+  given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn authorized(request: &HttpRequest, token: &str) -> bool {
+  request.headers().iter()
+    .find(|x| x.field.equiv("Authorization"))
+    .and_then(|x| x.value.as_str().strip_prefix("Bearer "))
+    .map_or(false, |given| same_token(given.trim(), token))
+}
+
+/// Answers `POST /control/<channel>`, where the channel is its name or
+/// output and the body is an `Action`, by queuing the action for that
+/// channel's scheduler.
+pub fn handle(request: &mut HttpRequest, live: &Live) -> Response<Cursor<Vec<u8>>> {
+  let settings = live.settings();
+  let token = match &settings.http.token {
+    Some(token) => token,
+    None => return status::json(403, &json!({ "error": "remote control is off until http.token is set" })),
+  };
+
+  if !authorized(request, token.expose()) {
+    return status::json(401, &json!({ "error": "missing or wrong bearer token" }));
+  }
+
+  if *request.method() != Method::Post {
+    return status::json(405, &json!({ "error": "actions have to be POSTed" }));
+  }
+
+  let name = request.url().trim_start_matches("/control/").trim_end_matches('/').to_string();
+  let channel = match settings.channels.iter().find(|x| x.name() == name || x.output.to_string() == name) {
+    Some(channel) => channel,
+    None => return status::json(404, &json!({ "error": format!("there's no channel {}", name) })),
+  };
+
+  let mut body = String::new();
+  if let Err(err) = request.as_reader().read_to_string(&mut body) {
+    return status::json(400, &json!({ "error": err.to_string() }));
+  }
+
+  let action: Action = match serde_json::from_str(&body) {
+    Ok(action) => action,
+    Err(err) => return status::json(400, &json!({ "error": err.to_string() })),
+  };
+
+  if let Action::ForceTemplate { template: Some(template) } = &action {
+    if Template::named(&channel.template_root(), template).is_none() {
+      return status::json(400, &json!({ "error": format!("{} isn't a template name such as 12-16x9", template) }));
+    }
+  }
+
+  let request = Request {
+    action,
+    at: Local::now(),
+    from: request.remote_addr().to_string(),
+  };
+
+  info!("Queued \"{}\" for {}", request, channel.name());
+  let response = json!({ "channel": channel.name(), "queued": &request });
+  submit(channel.output, request);
+
+  status::json(202, &response)
+}
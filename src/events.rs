@@ -0,0 +1,165 @@
+use std::cell::Cell;
+use std::error::Error;
+use std::net::TcpListener;
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Sender, RecvTimeoutError};
+use std::time::Duration;
+use chrono::{DateTime, FixedOffset, Local};
+use lazy_static::lazy_static;
+use log::{info, debug, error};
+use serde::Serialize;
+use tungstenite::Message as WsMessage;
+
+use crate::logging;
+use crate::metrics;
+
+/// The JSON schema every message on the feed follows.
+pub const SCHEMA: &str = include_str!("../events.schema.json");
+
+/// Something that happened, tagged with its `type`. `events.schema.json`
+/// documents each one, so keep the two in step.
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+  ScheduleFetched { events: usize, degraded: bool },
+  /// The channel picked the next show to play out.
+  ShowArmed {
+    show: i64,
+    title: String,
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+    decision: String,
+    level: u8,
+  },
+  PreRollStarted { show: i64, start: DateTime<FixedOffset> },
+  TemplateActivated { show: i64, template: String },
+  ForceEventSent { event: String, attempt: u32 },
+  /// A dry run counts as succeeding without anything being sent.
+  ForceEventSucceeded { event: String, dry_run: bool },
+  ForceEventFailed { event: String, error: String },
+  FallbackEntered { reason: String },
+  /// The channel left the output alone for a show.
+  ShowUntouched { show: i64, reason: String },
+  #[serde(rename = "decklink_signal_lost")]
+  DeckLinkSignalLost,
+  OutputStopped,
+}
+
+/// An event as it goes out on the feed.
+#[derive(Debug, Serialize)]
+pub struct Message {
+  pub at: DateTime<Local>,
+  /// The channel it happened on, if it's one channel's doing.
+  pub channel: Option<String>,
+  #[serde(flatten)]
+  pub event: Event,
+}
+
+lazy_static! {
+  static ref SUBSCRIBERS: Mutex<Vec<Sender<String>>> = Mutex::new(Vec::new());
+}
+
+// What a channel thread last did with its scene.
+#[derive(Clone, Copy, PartialEq)]
+enum Scene {
+  Template,
+  Fallback,
+  Untouched,
+}
+
+thread_local! {
+  // So going back to the fallback scene, or leaving a show alone, over and
+  // over while waiting is only one event.
+  static SCENE: Cell<Scene> = Cell::new(Scene::Template);
+}
+
+/// Sends an event to everyone subscribed, as happening on the channel this
+/// thread is working on.
+pub fn publish(event: Event) {
+  send(logging::channel(), event);
+}
+
+/// Sends an event about `channel` from a thread that isn't the channel's.
+pub fn publish_for(channel: &str, event: Event) {
+  send(Some(channel.to_string()), event);
+}
+
+fn send(channel: Option<String>, event: Event) {
+  let scene = match &event {
+    Event::TemplateActivated { .. } => Some(Scene::Template),
+    Event::FallbackEntered { .. } => Some(Scene::Fallback),
+    Event::ShowUntouched { .. } => Some(Scene::Untouched),
+    _ => None,
+  };
+
+  if let Some(scene) = scene {
+    if SCENE.with(|x| x.replace(scene)) == scene && scene != Scene::Template {
+      return;
+    }
+  }
+
+  metrics::observe(channel.as_deref(), &event);
+
+  let message = Message { at: Local::now(), channel, event };
+  let json = match serde_json::to_string(&message) {
+    Ok(json) => json,
+    Err(err) => {
+      error!("Failed to serialize {:?}! {}", message, err);
+      return;
+    }
+  };
+
+  SUBSCRIBERS.lock().unwrap().retain(|x| x.send(json.clone()).is_ok());
+}
+
+// How long a quiet feed goes before a ping, so dead connections get noticed.
+const PING_SECS: u64 = 30;
+
+/// Serves the feed as a WebSocket on `bind` from a background thread. Every
+/// connection gets each event from then on as a JSON text message.
+pub fn serve(bind: &str) -> Result<(), Box<dyn Error>> {
+  let listener = TcpListener::bind(bind)?;
+  info!("Serving events on ws://{}", bind);
+
+  std::thread::spawn(move || {
+    for stream in listener.incoming() {
+      let stream = match stream {
+        Ok(stream) => stream,
+        Err(err) => {
+          error!("Failed to accept an events connection! {}", err);
+          continue;
+        }
+      };
+
+      std::thread::spawn(move || {
+        let peer = stream.peer_addr().map_or_else(|_| "unknown".to_string(), |x| x.to_string());
+        let mut socket = match tungstenite::accept(stream) {
+          Ok(socket) => socket,
+          Err(err) => {
+            debug!("Events handshake with {} failed! {}", peer, err);
+            return;
+          }
+        };
+
+        let (sender, receiver) = channel();
+        SUBSCRIBERS.lock().unwrap().push(sender);
+        debug!("{} subscribed to events", peer);
+
+        loop {
+          let message = match receiver.recv_timeout(Duration::from_secs(PING_SECS)) {
+            Ok(json) => WsMessage::Text(json),
+            Err(RecvTimeoutError::Timeout) => WsMessage::Ping(Vec::new()),
+            Err(RecvTimeoutError::Disconnected) => break,
+          };
+
+          if let Err(err) = socket.write_message(message) {
+            debug!("{} unsubscribed from events, {}", peer, err);
+            break;
+          }
+        }
+      });
+    }
+  });
+
+  Ok(())
+}
@@ -0,0 +1,247 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{File, OpenOptions, create_dir_all, rename, remove_file};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+use chrono::Local;
+use lazy_static::lazy_static;
+use log::{Log, Metadata, Record, LevelFilter};
+use serde::{Serialize, Deserialize};
+
+/// The parts of scissors whose verbosity can be set on their own, with the
+/// log targets each one covers.
+pub const MODULES: [(&str, &[&str]); 4] = [
+  ("cablecast", &["scissors::cablecast"]),
+  ("scheduler", &["scissors", "scissors::schedule"]),
+  ("obs", &["scissors::obs", "obs"]),
+  ("templates", &["scissors::template"]),
+];
+
+fn default_max_file_size() -> u64 {
+  10 * 1024 * 1024
+}
+
+fn default_keep_files() -> usize {
+  10
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LogSettings {
+  /// Folder for the log files, Documents/scissors-logs by default.
+  pub directory: Option<PathBuf>,
+  /// How big `scissors.log` gets before it's rotated out, in bytes.
+  #[serde(default = "default_max_file_size")]
+  pub max_file_size: u64,
+  /// How many rotated files to keep around.
+  #[serde(default = "default_keep_files")]
+  pub keep_files: usize,
+  /// Verbosity for each of `MODULES`, overriding `--log-level`.
+  #[serde(default)]
+  pub levels: HashMap<String, LevelFilter>,
+}
+
+impl Default for LogSettings {
+  fn default() -> LogSettings {
+    LogSettings {
+      directory: None,
+      max_file_size: default_max_file_size(),
+      keep_files: default_keep_files(),
+      levels: HashMap::new(),
+    }
+  }
+}
+
+impl LogSettings {
+  pub fn directory(&self) -> PathBuf {
+    self.directory.clone().unwrap_or_else(|| {
+      let mut path = dirs::document_dir().unwrap();
+      path.push("scissors-logs");
+      path
+    })
+  }
+}
+
+// `scissors.log`, moved to `scissors.1.log` and so on once it's full.
+struct LogFile {
+  directory: PathBuf,
+  file: File,
+  size: u64,
+  max_size: u64,
+  keep: usize,
+}
+
+impl LogFile {
+  fn open(settings: &LogSettings) -> Result<LogFile, Box<dyn Error>> {
+    let directory = settings.directory();
+    create_dir_all(&directory)?;
+
+    let file = OpenOptions::new().create(true).append(true).open(directory.join("scissors.log"))?;
+    let size = file.metadata()?.len();
+
+    Ok(LogFile {
+      directory,
+      file,
+      size,
+      max_size: settings.max_file_size,
+      keep: settings.keep_files,
+    })
+  }
+
+  fn rotated(&self, i: usize) -> PathBuf {
+    self.directory.join(format!("scissors.{}.log", i))
+  }
+
+  fn rotate(&mut self) -> Result<(), Box<dyn Error>> {
+    let _ = remove_file(self.rotated(self.keep));
+    for i in (1..self.keep).rev() {
+      let _ = rename(self.rotated(i), self.rotated(i + 1));
+    }
+
+    let current = self.directory.join("scissors.log");
+    if self.keep > 0 {
+      rename(&current, self.rotated(1))?;
+    } else {
+      remove_file(&current)?;
+    }
+
+    self.file = OpenOptions::new().create(true).append(true).open(current)?;
+    self.size = 0;
+    Ok(())
+  }
+
+  fn write(&mut self, line: &str) {
+    if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+      if let Err(err) = self.rotate() {
+        eprintln!("Failed to rotate the log file! {}", err);
+      }
+    }
+
+    if self.file.write_all(line.as_bytes()).is_ok() {
+      self.size += line.len() as u64;
+    }
+  }
+}
+
+// What the current thread is working on, added to everything it logs.
+#[derive(Default)]
+struct Context {
+  channel: Option<String>,
+  show: Option<i64>,
+}
+
+thread_local! {
+  static CONTEXT: RefCell<Context> = RefCell::new(Context::default());
+}
+
+/// Tags everything this thread logs from now on with the channel's name.
+pub fn set_channel(name: &str) {
+  CONTEXT.with(|x| x.borrow_mut().channel = Some(name.to_string()));
+}
+
+/// The name of the channel this thread is working on, if it's a channel's.
+pub fn channel() -> Option<String> {
+  CONTEXT.with(|x| x.borrow().channel.clone())
+}
+
+/// Tags everything this thread logs from now on with the show's id, or stops
+/// tagging it.
+pub fn set_show(show: Option<i64>) {
+  CONTEXT.with(|x| x.borrow_mut().show = show);
+}
+
+struct Logger {
+  default: RwLock<LevelFilter>,
+  levels: RwLock<HashMap<String, LevelFilter>>,
+  file: Mutex<Option<LogFile>>,
+}
+
+lazy_static! {
+  static ref LOGGER: Logger = Logger {
+    default: RwLock::new(LevelFilter::Info),
+    levels: RwLock::new(HashMap::new()),
+    file: Mutex::new(None),
+  };
+}
+
+impl Logger {
+  fn level(&self, target: &str) -> LevelFilter {
+    let levels = self.levels.read().unwrap();
+    MODULES.iter()
+      .find(|(_, targets)| targets.contains(&target))
+      .and_then(|(module, _)| levels.get(*module).cloned())
+      .unwrap_or(*self.default.read().unwrap())
+  }
+
+  // The most verbose of all the levels, for `log` to skip anything quieter
+  // before it gets here.
+  fn max_level(&self) -> LevelFilter {
+    let levels = self.levels.read().unwrap();
+    levels.values().cloned().chain(std::iter::once(*self.default.read().unwrap())).max().unwrap()
+  }
+}
+
+impl Log for Logger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    metadata.level() <= self.level(metadata.target())
+  }
+
+  fn log(&self, record: &Record) {
+    if !self.enabled(record.metadata()) {
+      return;
+    }
+
+    let fields = CONTEXT.with(|x| {
+      let context = x.borrow();
+      let mut fields = String::new();
+      if let Some(channel) = &context.channel {
+        fields.push_str(&format!(" channel={:?}", channel));
+      }
+      if let Some(show) = context.show {
+        fields.push_str(&format!(" show={}", show));
+      }
+      fields
+    });
+
+    let line = format!(
+      "{} {:5} {}{}: {}\n",
+      Local::now().format("%Y-%m-%dT%H:%M:%S%.3f%:z"),
+      record.level(),
+      record.target(),
+      fields,
+      record.args(),
+    );
+
+    print!("{}", line);
+    if let Some(file) = self.file.lock().unwrap().as_mut() {
+      file.write(&line);
+    }
+  }
+
+  fn flush(&self) {
+    if let Some(file) = self.file.lock().unwrap().as_mut() {
+      let _ = file.file.flush();
+    }
+  }
+}
+
+/// Starts logging to the console at `level`.
+pub fn init(level: LevelFilter) {
+  *LOGGER.default.write().unwrap() = level;
+  log::set_logger(&*LOGGER).expect("the logger is only set up once");
+  log::set_max_level(LOGGER.max_level());
+}
+
+/// Applies the config's per-module levels, and starts writing to log files
+/// if `files` is set.
+pub fn configure(settings: &LogSettings, files: bool) -> Result<(), Box<dyn Error>> {
+  *LOGGER.levels.write().unwrap() = settings.levels.clone();
+  log::set_max_level(LOGGER.max_level());
+
+  if files {
+    *LOGGER.file.lock().unwrap() = Some(LogFile::open(settings)?);
+  }
+
+  Ok(())
+}
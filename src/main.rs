@@ -1,14 +1,10 @@
-use std::fmt;
 use std::error::Error;
+use std::cell::Cell;
 use std::os::raw::c_char;
-use std::fs::{File, create_dir};
-use std::io::prelude::*;
-use std::collections::HashSet;
-use serde::{Serialize, Deserialize};
+use std::fs::create_dir;
+use std::path::{Path, PathBuf};
 use tempfile::tempdir;
-use scraper::{Html, Selector};
-use fs_extra::dir::{copy, CopyOptions};
-use chrono::{DateTime, Local, Duration};
+use chrono::{DateTime, FixedOffset, Local, Duration};
 use winit::{
   event::{Event, WindowEvent},
   event_loop::{ControlFlow, EventLoop},
@@ -17,432 +13,1049 @@ use winit::{
   dpi::PhysicalSize,
 };
 
+use structopt::StructOpt;
+use log::{trace, debug, info, warn, error, LevelFilter};
+
 mod obs;
-use obs::{Scene, Source, Data, Output};
+use obs::{Scene, Source, Data, Output, View};
+
+mod cablecast;
+use cablecast::{OverrideGuard, EventSummary};
+
+mod config;
+use config::{Settings, ChannelSettings, Live};
+
+mod schedule;
+use schedule::Change;
+
+mod template;
+use template::{Template, Decision};
+
+mod wizard;
+
+mod logging;
+
+mod asrun;
+
+mod status;
+use status::{State, ShowStatus};
+
+mod control;
+use control::{Action, Controls, ForcedTemplate, ShowKey};
+
+mod events;
+
+mod metrics;
+
+mod alerts;
+
+// How long after the scheduled start a switch may still be sent.
+const SWITCH_GRACE_SECS: i64 = 10;
+
+// How long the router gets to report the new device after a switch.
+const SWITCH_CONFIRM_SECS: i64 = 15;
+
+/// Waits until `until` for the router to report the switch. A switch that
+/// can't be confirmed goes into the as-run record and the channel's status,
+/// and is alerted on.
+fn confirm_switch(config: &Settings, channel: &ChannelSettings, until: DateTime<Local>) -> bool {
+  if config.dry_run {
+    info!("Dry run, not confirming switch of output {}", channel.output);
+    return true;
+  }
 
-#[derive(Debug, Deserialize, Clone)]
-struct Settings {
-  decklink_input: Option<String>,
-  decklink_output: Option<String>,
-  cablecast_url: String,
-  location: i64,
-  channel: i64,
-  output: i64,
-  device: i64,
-  projects: HashSet::<i64>,
-  username: String,
-  password: String,
+  let problem = match cablecast::confirm_switch(config, channel, until) {
+    Ok(true) => {
+      info!("Output {} confirmed on device {}", channel.output, channel.device);
+      alerts::resolve(alerts::Kind::SwitchUnconfirmed, &channel.name());
+      return true;
+    }
+    Ok(false) => format!("output {} did not switch to device {} by {}", channel.output, channel.device, until.to_rfc3339()),
+    Err(err) => format!("could not confirm switch of output {}, {}", channel.output, err),
+  };
+
+  error!("{}", problem);
+  asrun::update(|record| record.errors.push(problem.clone()));
+  status::update(channel.output, |status| status.last_error = Some(status::LastError { at: Local::now(), message: problem.clone() }));
+  alerts::raise(alerts::Kind::SwitchUnconfirmed, &channel.name(), problem);
+
+  false
 }
 
+// Cablecast's `Date` header only has whole seconds, and the request takes
+// time of its own, so offsets under this are noise rather than skew.
+const CLOCK_SKEW_NOISE_MS: i64 = 1000;
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct EventSummaries {
-  event_summaries: Vec<EventSummary>,
-  shows: Vec<Show>,
-  digital_files: Vec<DigitalFile>,
+thread_local! {
+  // The skew last warned about, in whole seconds, so polling with the same
+  // skew doesn't warn every time.
+  static WARNED_SKEW: Cell<Option<i64>> = Cell::new(None);
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct EventSummary {
-  location: i64,
-  channel: i64,
-  show: i64,
-  start: DateTime<Local>,
-  end: DateTime<Local>,
+fn clock_skew(config: &Settings, offset: Option<Duration>) -> Duration {
+  let offset = match offset {
+    Some(offset) if offset.num_milliseconds().abs() > CLOCK_SKEW_NOISE_MS => offset,
+    _ => return Duration::zero(),
+  };
+
+  let secs = offset.num_seconds();
+  if secs.abs() > config.max_clock_skew_secs && WARNED_SKEW.with(|x| x.replace(Some(secs))) != Some(secs) {
+    warn!("Cablecast's clock is {}ms off from ours, more than the {}s allowed", offset.num_milliseconds(), config.max_clock_skew_secs);
+  }
+
+  offset
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct Show {
-  id: i64,
-  cg_title: String,
-  project: Option<i64>,
-  event_date: DateTime<Local>,
+fn fallback_scene(view: &View, vi_source: &Source, channel: &ChannelSettings) -> Result<(), Box<dyn Error>> {
+  let scene = Scene::new(&format!("{} fallback scene", channel.name()))?;
+
+  let item = scene.add(vi_source)?;
+  item.set_scale(1.0, 1.0);
+  item.set_pos(0.0, 0.0);
+
+  view.set_source(0, &scene.get_source()?);
+
+  Ok(())
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct DigitalFile {
-  show: i64,
-  aspect_ratio: i64,
+fn fallback(view: &View, vi_source: &Source, channel: &ChannelSettings, reason: &str) -> Result<(), Box<dyn Error>> {
+  fallback_scene(view, vi_source, channel)?;
+  events::publish(events::Event::FallbackEntered { reason: reason.to_string() });
+
+  Ok(())
 }
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct ForceEvents {
-  force_events: Vec<ForceEvent>,
+/// Lays the channel's video over a rendered template, where the template's
+/// `#VIDEO` rect says it goes.
+fn template_scene(vi_source: &Source, name: &str, template: &Template, dir: &Path, cg_title: &str, event_date: &str) -> Result<Scene, Box<dyn Error>> {
+  let rect = template.video_rect()?;
+  let page = template.render(dir, cg_title, event_date)?;
+
+  let scene = Scene::new(&format!("{} main scene", name))?;
+
+  let settings = Data::new()?;
+  settings.set_bool("is_local_file", true)?;
+  settings.set_string("local_file", page.to_str().unwrap())?;
+  settings.set_int("width", 1920)?;
+  settings.set_int("height", 1080)?;
+  let bg_source = Source::new("browser_source", &format!("{} background", name), Some(&settings), None)?;
+
+  let item = scene.add(&bg_source)?;
+  item.set_scale(1.0, 1.0);
+  item.set_pos(0.0, 0.0);
+
+  let item = scene.add(vi_source)?;
+  item.set_scale(rect.width / if template.is_4by3 { 1440.0 } else { 1920.0 }, rect.height / 1080.0);
+  item.set_pos(rect.x, rect.y);
+
+  if template.is_4by3 {
+    item.set_crop(240, 0, 240, 0);
+  }
+
+  Ok(scene)
 }
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct ForceEvent {
-  switch_event: Option<SwitchEvent>,
-  automation_override: Option<AutomationOverride>,
+fn report(channel: &ChannelSettings, state: State, current: Option<ShowStatus>, next: Option<ShowStatus>, decision: Option<&Decision>) {
+  status::update(channel.output, |status| {
+    status.state = state;
+    status.current_show = current;
+    status.next_show = next;
+    status.template = decision.and_then(Decision::template).map(|x| x.name.clone());
+    status.level = decision.map(Decision::level);
+  });
 }
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct SwitchEvent {
-  device: i64,
-  output: i64,
+/// Puts up the scene for `decision`, dropping to the fallback scene if the
+/// template can't be used after all. Returns the level the show ended up at.
+fn frame(view: &View, vi_source: &Source, channel: &ChannelSettings, event: &schedule::Event, decision: &Decision, dir: &Path) -> Result<u8, Box<dyn Error>> {
+  match decision {
+    Decision::Template(template) => {
+      let event_date = channel.format_time(&event.show.event_date, "%B %d, %Y");
+      let started = std::time::Instant::now();
+      match template_scene(vi_source, &channel.name(), template, dir, &event.show.cg_title, &event_date) {
+        Ok(scene) => {
+          metrics::template_rendered(&channel.name(), started.elapsed());
+          view.set_source(0, &scene.get_source()?);
+          events::publish(events::Event::TemplateActivated { show: event.show.id, template: template.name.clone() });
+        }
+        Err(err) => {
+          fallback(view, vi_source, channel, &err.to_string())?;
+          error!("Could not use template {}! {}", template.name, err);
+          asrun::update(|record| {
+            record.level = 1;
+            record.decision = format!("fallback scene, {}", err);
+          });
+          status::update(channel.output, |status| status.level = Some(1));
+          return Ok(1);
+        }
+      }
+    }
+    // Neither is a failure: a missing template is alerted on when the show
+    // is armed, and leaving a show alone is what was asked for.
+    Decision::Fallback { .. } => {
+      fallback(view, vi_source, channel, &decision.to_string())?;
+      warn!("{}", decision);
+    }
+    Decision::Untouched { .. } => {
+      fallback(view, vi_source, channel, &decision.to_string())?;
+      info!("{}", decision);
+    }
+  }
+
+  Ok(decision.level())
 }
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct AutomationOverride {
-  r#override: bool,
-  output: i64,
-  do_last_switch_on_resume: bool,
+/// What's left for `show_loop` to do after an operator's request.
+enum Then {
+  CarryOn,
+  /// Put the show on air back up, since how it's framed may have changed.
+  Reframe,
+  SetupIo,
 }
 
-#[derive(Debug, Clone)]
-pub struct EventSummaryMissing;
+/// Carries out an operator's request on `event`, which is the show on air or
+/// else the one armed. `following` is the show a skip applies to.
+fn operate(config: &Settings, channel: &ChannelSettings, controls: &mut Controls, request: &control::Request, event: &schedule::Event, following: Option<ShowKey>, io_since: DateTime<Local>) -> Result<Then, Box<dyn Error>> {
+  info!("Operator asked to {}", request);
 
-impl fmt::Display for EventSummaryMissing {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "the event summary for the channel is missing")
+  match &request.action {
+    Action::ForceFallback { on } => controls.force_fallback = *on,
+    Action::ForceTemplate { template } => {
+      controls.template = template.clone().map(|template| ForcedTemplate { event: ShowKey::of(event), template });
+    }
+    Action::SkipNext => controls.skip = Some(following.ok_or("there's no next show to skip")?),
+    Action::Hold { on } => controls.hold = *on,
+    // Asked for before I/O was last set up, so it's already been done.
+    Action::SetupIo if request.at < io_since => {}
+    Action::SetupIo => return Ok(Then::SetupIo),
+    Action::ReleaseOverride => cablecast::release_override(config, channel.output)?,
+    Action::ReloadTemplates => {}
   }
-}
 
-impl Error for EventSummaryMissing {
-  fn source(&self) -> Option<&(dyn Error + 'static)> {
-    None
+  status::update(channel.output, |status| status.controls = controls.clone());
+
+  match &request.action {
+    Action::ForceFallback { .. } | Action::ForceTemplate { .. } | Action::ReloadTemplates => Ok(Then::Reframe),
+    _ => Ok(Then::CarryOn),
   }
 }
 
-#[derive(Debug, Clone)]
-pub struct ShowMissing;
+/// Gives an operator's request its own line in the as-run log, showing how
+/// `event` will play out now.
+fn record_request(channel: &ChannelSettings, event: &schedule::Event, decision: &Decision, request: &control::Request, result: &Result<Then, Box<dyn Error>>) {
+  let mut record = as_run_record(channel, event, decision);
+  record.action = Some(request.to_string());
+  if let Err(err) = result {
+    error!("Could not {}! {}", request.action, err);
+    record.errors.push(err.to_string());
+  }
 
-impl fmt::Display for ShowMissing {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "the show for the current event is missing")
+  if let Err(err) = asrun::write(&record) {
+    error!("Failed to write the as-run log! {}", err);
   }
 }
 
-impl Error for ShowMissing {
-  fn source(&self) -> Option<&(dyn Error + 'static)> {
-    None
+fn as_run_record(channel: &ChannelSettings, event: &schedule::Event, decision: &Decision) -> asrun::Record {
+  asrun::Record {
+    logged_at: Local::now(),
+    channel: channel.name(),
+    output: channel.output,
+    show: event.show.id,
+    title: event.show.cg_title.clone(),
+    scheduled_start: event.summary.start,
+    scheduled_end: event.program_end(),
+    cue_points: event.cue_points(),
+    actual_start: None,
+    actual_end: None,
+    template: decision.template().map(|x| x.name.clone()),
+    level: decision.level(),
+    decision: decision.to_string(),
+    force_events: Vec::new(),
+    switch_confirmed: None,
+    errors: Vec::new(),
+    action: None,
   }
 }
 
-#[derive(Debug, Clone)]
-pub struct DigitalFileMissing;
+fn take_output(config: &Settings, channel: &ChannelSettings, start: DateTime<FixedOffset>, end: DateTime<FixedOffset>) -> Result<(), Box<dyn Error>> {
+  let time_to_show = Local::now().signed_duration_since(start).num_seconds();
+  if time_to_show >= 0 {
+    std::thread::sleep(Duration::seconds(1).to_std()?);
 
-impl fmt::Display for DigitalFileMissing {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "the digital file for the show is missing")
-  }
-}
+    // Joining a show that is already on air, so the switch is still worth
+    // making for as long as the show lasts.
+    cablecast::switch(config, channel, end.with_timezone(&Local))?;
+    let switched_at = Local::now();
+    let confirmed = confirm_switch(config, channel, switched_at + Duration::seconds(SWITCH_CONFIRM_SECS));
+    asrun::update(|record| {
+      record.actual_start = Some(switched_at);
+      record.switch_confirmed = Some(confirmed);
+    });
+  } else {
+    let guard = OverrideGuard::engage(config, channel, start.with_timezone(&Local))?;
+
+    let until_start = start.signed_duration_since(Local::now());
+    std::thread::sleep(until_start.to_std()?);
+
+    cablecast::switch(config, channel, (start + Duration::seconds(SWITCH_GRACE_SECS)).with_timezone(&Local))?;
+    let switched_at = Local::now();
+    metrics::switch_delay(&channel.name(), switched_at.signed_duration_since(start));
+
+    // Confirming can't hold the override any longer than the switch would
+    // have anyway.
+    let resume = switched_at + Duration::seconds(SWITCH_GRACE_SECS);
+    let confirmed = confirm_switch(config, channel, std::cmp::min(resume, switched_at + Duration::seconds(SWITCH_CONFIRM_SECS)));
+    asrun::update(|record| {
+      record.actual_start = Some(switched_at);
+      record.switch_confirmed = Some(confirmed);
+    });
+
+    let until_resume = resume - Local::now();
+    if until_resume > Duration::zero() {
+      std::thread::sleep(until_resume.to_std()?);
+    }
 
-impl Error for DigitalFileMissing {
-  fn source(&self) -> Option<&(dyn Error + 'static)> {
-    None
+    guard.release()?;
   }
+
+  Ok(())
 }
 
-#[derive(Debug, Clone)]
-pub struct ForceEventFailed;
+// How often to look for schedule changes while waiting on an armed event.
+const WATCH_INTERVAL_SECS: i64 = 30;
 
-impl fmt::Display for ForceEventFailed {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "the force event call failed")
-  }
+/// Why `watch_until` came back.
+enum Woken {
+  Due,
+  Changed(Vec<Change>),
+  /// There are operator requests to pick up with `control::take`.
+  Operator,
 }
 
-impl Error for ForceEventFailed {
-  fn source(&self) -> Option<&(dyn Error + 'static)> {
-    None
+/// Waits until `until`, polling Cablecast for changes to `armed` along the
+/// way. Returns early as soon as anything changes or an operator asks for
+/// something.
+fn watch_until(config: &Settings, channel: &ChannelSettings, armed: &EventSummary, skew: Duration, until: DateTime<FixedOffset>) -> Result<Woken, Box<dyn Error>> {
+  loop {
+    let remaining = until.signed_duration_since(Local::now());
+    if remaining <= Duration::zero() {
+      return Ok(Woken::Due);
+    }
+
+    if control::wait(channel.output, std::cmp::min(remaining, Duration::seconds(WATCH_INTERVAL_SECS)).to_std()?) {
+      return Ok(Woken::Operator);
+    }
+
+    if until.signed_duration_since(Local::now()) <= Duration::zero() {
+      return Ok(Woken::Due);
+    }
+
+    match schedule::fetch(config) {
+      Ok(schedule) => {
+        let changes = schedule.changes(channel, skew, armed);
+        for change in &changes {
+          info!("Show {} changed: {}", armed.show, change);
+        }
+
+        if !changes.is_empty() {
+          return Ok(Woken::Changed(changes));
+        }
+      }
+      Err(err) => warn!("Failed to check for schedule changes! {}", err),
+    }
   }
 }
 
-fn fallback(vi_source: &Source) -> Result<(), Box<dyn Error>> {
-  let scene = Scene::new("fallback scene")?;
+/// Plays out the channel's shows until the DeckLink devices `io` was set up
+/// with change in the config, or an operator asks for I/O to be set up
+/// again. With `force_fallback`, every show is left on the fallback scene.
+fn show_loop(view: &View, vi_source: &Source, live: &Live, io: &ChannelSettings, controls: &mut Controls, force_fallback: bool) -> Result<(), Box<dyn Error>> {
+  let io_since = Local::now();
+
+  // The last event left alone that went into the as-run log, since it's seen
+  // again every time around.
+  let mut untouched_logged = None;
+  // The last event armed, so it's only announced once.
+  let mut last_armed = None;
+
+  'events: loop {
+    // Between events is the one point a reloaded config can be picked up
+    // without disturbing a show.
+    let (settings, current) = live.channel(io.output).unwrap_or_else(|| (live.settings(), io.clone()));
+    let (config, channel) = (&settings, &current);
+
+    if channel.decklink_input != io.decklink_input || channel.decklink_output != io.decklink_output {
+      info!("Setting up I/O again for the new DeckLink devices");
+      return Ok(());
+    }
 
-  let item = scene.add(vi_source)?;
-  item.set_scale(1.0, 1.0);
-  item.set_pos(0.0, 0.0);
+    let schedule = schedule::fetch(config)?;
+    let skew = clock_skew(config, schedule.offset);
 
-  obs::set_output_source(0, &scene.get_source()?);
+    trace!("{:?}", schedule);
 
-  Ok(())
-}
+    let event = schedule.next_event(channel, skew)?;
+    let (summary, show, file) = (event.summary, event.show, event.file);
 
-fn show_loop(vi_source: &Source, config: &Settings) -> Result<(), Box<dyn Error>> {
-  loop {
-    let url = format!("https://{}/CablecastAPI/v1/eventsummaries?future=true&include=show%2Cdigitalfile%2Cmedia%2Creel&limit_per_channel=1", config.cablecast_url);
-    let resp = reqwest::blocking::get(&url)?.json::<EventSummaries>()?;
+    // When the show starts and its content ends by our clock rather than
+    // Cablecast's.
+    let content_length = event.content_length();
+    let start = summary.start - skew;
+    let end = event.program_end() - skew;
 
-    println!("{:?}", resp);
+    logging::set_show(Some(show.id));
+    trace!("{:?}", summary);
+    trace!("{:?}", show);
+    trace!("{:?}", file);
 
-    let summary = resp.event_summaries.iter().find(|x| x.location == config.location && x.channel == config.channel).ok_or(EventSummaryMissing)?;
-    let show = resp.shows.iter().find(|x| x.id == summary.show).ok_or(ShowMissing)?;
-    let file = resp.digital_files.iter().find(|x| x.show == summary.show).ok_or(DigitalFileMissing)?;
+    for reel in &event.reels {
+      let length = event.reel_length(reel).map_or_else(|| "an unknown time".to_string(), |x| format!("{}s", x.num_seconds()));
+      debug!("Reel {}: media {:?} from {}s for {}", reel.id, reel.media, reel.in_point().num_seconds(), length);
+    }
 
-    let is_4by3 = file.aspect_ratio == 1;
-    // let is_4by3 = true;
+    for cue_point in event.cue_points() {
+      debug!("Reel boundary at {}", channel.format_time(&cue_point, "%a, %d %b %Y %H:%M:%S %Z"));
+    }
 
-    println!("{:?}", summary);
-    println!("{:?}", show);
-    println!("{:?}", file);
+    // Operators' requests are picked up here, or while the show is on air,
+    // so they never land in the middle of a switch.
+    let armed = ShowKey::of(&event);
+    controls.expire(&armed);
+    for request in control::take(channel.output) {
+      let result = operate(config, channel, controls, &request, &event, Some(armed), io_since);
+      record_request(channel, &event, &controls.decide(channel, &event, force_fallback), &request, &result);
+      if let Ok(Then::SetupIo) = result {
+        info!("Setting up I/O again for the operator");
+        return Ok(());
+      }
+    }
 
-    if !config.projects.contains(&show.project.unwrap_or(-1)) {
-      fallback(vi_source)?;
+    if controls.hold {
+      report(channel, State::Held, None, Some(ShowStatus::new(&event)), None);
+      info!("Holding the scene, arming nothing until the operator lets go");
+      control::wait(channel.output, Duration::minutes(5).to_std()?);
+      continue;
+    }
 
-      println!("wrong format for show {}", show.id);
-      println!("sleeping for 5 minutes");
-      println!("Next show at {}", summary.start.to_rfc2822());
-      std::thread::sleep(Duration::minutes(5).to_std()?);
+    let decision = controls.decide(channel, &event, force_fallback);
+    if last_armed != Some(armed) {
+      events::publish(events::Event::ShowArmed {
+        show: show.id,
+        title: show.cg_title.clone(),
+        start: summary.start,
+        end: event.program_end(),
+        decision: decision.to_string(),
+        level: decision.level(),
+      });
+      last_armed = Some(armed);
+
+      match &decision {
+        Decision::Fallback { reason, .. } => alerts::raise(alerts::Kind::TemplateMissing, &channel.name(),
+          format!("Show {} \"{}\" at {} will play on the fallback scene, {}", show.id, show.cg_title, channel.format_time(&summary.start, "%a, %d %b %Y %H:%M:%S %Z"), reason)),
+        Decision::Template(_) => alerts::resolve(alerts::Kind::TemplateMissing, &channel.name()),
+        Decision::Untouched { .. } => {}
+      }
+    }
+
+    if let Decision::Untouched { reason } = &decision {
+      // The output isn't switched, so the fallback scene is only up in
+      // the preview rather than on air.
+      fallback_scene(view, vi_source, channel)?;
+      events::publish(events::Event::ShowUntouched { show: show.id, reason: reason.clone() });
+
+      if Local::now() >= start {
+        report(channel, State::Untouched, Some(ShowStatus::new(&event)), None, Some(&decision));
+      } else {
+        report(channel, State::Untouched, None, Some(ShowStatus::new(&event)), Some(&decision));
+      }
+
+      let key = (show.id, summary.start);
+      if Local::now() >= start && untouched_logged != Some(key) {
+        asrun::begin(as_run_record(channel, &event, &decision));
+        asrun::finish();
+        untouched_logged = Some(key);
+      }
+
+      info!("leaving show {} alone, {}", show.id, reason);
+      info!("sleeping for 5 minutes");
+      info!("Next show at {}", channel.format_time(&summary.start, "%a, %d %b %Y %H:%M:%S %Z"));
+      watch_until(config, channel, summary, skew, (Local::now() + Duration::minutes(5)).into())?;
       continue;
     }
 
-    let time_to_show = Local::now().signed_duration_since::<Local>(summary.start + Duration::seconds(-20)).num_seconds();
+    let time_to_show = Local::now().signed_duration_since(start + Duration::seconds(-20)).num_seconds();
     if time_to_show > -300 && time_to_show < -10 {
-      fallback(vi_source)?;
-
-      println!("sleeping until start");
-      println!("Next show at {}", summary.start.to_rfc2822());
-      let duration = summary.start.signed_duration_since(Local::now()) + Duration::seconds(-20);
-      std::thread::sleep(duration.to_std()?);
+      fallback(view, vi_source, channel, &format!("waiting for show {}", show.id))?;
+      events::publish(events::Event::PreRollStarted { show: show.id, start: summary.start });
+      report(channel, State::PreRoll, None, Some(ShowStatus::new(&event)), None);
+
+      info!("sleeping until start");
+      info!("Next show at {}", channel.format_time(&summary.start, "%a, %d %b %Y %H:%M:%S %Z"));
+      match watch_until(config, channel, summary, skew, start + Duration::seconds(-20))? {
+        Woken::Due => {}
+        Woken::Changed(_) => {
+          info!("Re-arming");
+          continue;
+        }
+        Woken::Operator => continue,
+      }
     } else if time_to_show < -10 {
-      fallback(vi_source)?;
+      fallback(view, vi_source, channel, &format!("waiting for show {}", show.id))?;
+      report(channel, State::Waiting, None, Some(ShowStatus::new(&event)), None);
 
-      println!("sleeping for 5 minutes");
-      println!("Next show at {}", summary.start.to_rfc2822());
-      std::thread::sleep(Duration::minutes(5).to_std()?);
+      info!("sleeping for 5 minutes");
+      info!("Next show at {}", channel.format_time(&summary.start, "%a, %d %b %Y %H:%M:%S %Z"));
+      watch_until(config, channel, summary, skew, (Local::now() + Duration::minutes(5)).into())?;
       continue;
     }
 
-    println!("{:?}", Local::now().signed_duration_since(summary.start));
-    println!("{:?}", Local::now().signed_duration_since(summary.end));
+    debug!("{:?}", Local::now().signed_duration_since(start));
+    debug!("{:?}", Local::now().signed_duration_since(end));
 
-    let mut path = dirs::document_dir().unwrap();
-    let mut folder_path = path.clone();
-    path.push("scissors-templates");
-    folder_path.push("scissors-templates");
-    if !path.exists() {
-      create_dir(path.clone())?
+    let root = channel.template_root();
+    if !root.exists() {
+      create_dir(&root)?
     }
 
-    let folder_name;
-    let project = show.project.unwrap();
-    if is_4by3 {
-      folder_name = format!("{}-4x3", project);
-      path.push(format!("{}-4x3", project));
-      folder_path.push(format!("{}-4x3", project));
-      path.push(format!("{}-4x3.html", project));
-    } else {
-      folder_name = format!("{}-16x9", project);
-      path.push(format!("{}-16x9", project));
-      folder_path.push(format!("{}-16x9", project));
-      path.push(format!("{}-16x9.html", project));
-    }
-
-    let dir = tempdir()?;
-
-    if path.exists() {
-      let scene = Scene::new("main scene")?;
-
-      let opt = CopyOptions::new();
-      copy(folder_path, dir.path(), &opt)?;
-
-      let mut html = String::new();
-      File::open(path)?.read_to_string(&mut html)?;
-      html = html.replace("{{cg_title}}", &show.cg_title);
-      html = html.replace("{{event_date}}", &show.event_date.format("%B %d, %Y").to_string());
-
-      let template_name = dir.path().join(&folder_name).join("template.html");
-
-      let mut f = File::create(&template_name)?;
-      f.write_all(html.as_bytes())?;
-
-      let mut x = 0.0;
-      let mut y = 0.0;
-      let mut width = 0.0;
-      let mut height = 0.0;
-
-      let mut svg = String::new();
-      File::open(dir.path().join(&folder_name).join(format!("{}.svg", folder_name)))?.read_to_string(&mut svg)?;
-      let document = Html::parse_fragment(&svg);
-      // println!("{:#?}", Selector::parse("#VIDEO"));
-      let selector = Selector::parse("#VIDEO").unwrap();
-      let element = document.select(&selector).next();
-      if let Some(element) = element {
-        x = element.value().attr("x").unwrap().parse::<f32>().unwrap();
-        y = element.value().attr("y").unwrap().parse::<f32>().unwrap();
-        width = element.value().attr("width").unwrap().parse::<f32>().unwrap();
-        height = element.value().attr("height").unwrap().parse::<f32>().unwrap();
-      }
+    // The rendered template has to outlive the show, since the browser source
+    // keeps loading from it.
+    let mut dir = tempdir()?;
 
-      let settings = Data::new()?;
-      settings.set_bool("is_local_file", true)?;
-      settings.set_string("local_file", template_name.to_str().unwrap())?;
-      settings.set_int("width", 1920)?;
-      settings.set_int("height", 1080)?;
-      let bg_source = Source::new("browser_source", "background", Some(&settings), None)?;
-
-      let item = scene.add(&bg_source)?;
-      item.set_scale(1.0, 1.0);
-      item.set_pos(0.0, 0.0);
-
-      let item = scene.add(&vi_source)?;
-      item.set_scale(width / if is_4by3 { 1440.0 } else { 1920.0 }, height / 1080.0);
-      item.set_pos(x, y);
-
-      if is_4by3 {
-        item.set_crop(240, 0, 240, 0);
+    // Decided again since the templates could have changed while waiting.
+    let decision = controls.decide(channel, &event, force_fallback);
+    asrun::begin(as_run_record(channel, &event, &decision));
+
+    let following = schedule.upcoming(channel, skew).get(1).map(|x| ShowKey { show: x.show, start: x.start });
+    let next = schedule.upcoming(channel, skew).get(1).and_then(|x| schedule.event(x).ok()).map(|x| ShowStatus::new(&x));
+    report(channel, State::OnAir, Some(ShowStatus::new(&event)), next.clone(), Some(&decision));
+    let level = frame(view, vi_source, channel, &event, &decision, dir.path())?;
+
+    // Level 2 is a show left alone on purpose, by the config or an operator.
+    if level == 1 {
+      alerts::raise(alerts::Kind::StartedInFallback, &channel.name(), format!("Show {} \"{}\" started on the fallback scene, {}", show.id, show.cg_title, decision));
+    } else if level == 0 {
+      alerts::resolve(alerts::Kind::StartedInFallback, &channel.name());
+    }
+
+    // Without Cablecast there's nothing to switch, but the template can stay
+    // up for the rest of the show instead of dropping back to fallback.
+    if let Err(err) = take_output(config, channel, start, end) {
+      // `channel_loop` records the errors that end the show.
+      if !schedule.degraded {
+        return Err(err);
       }
 
-      obs::set_output_source(0, &scene.get_source()?);
-    } else {
-      fallback(&vi_source)?;
-      println!("Error: Could not find {}", path.to_str().unwrap());
-    }
-
-    let time_to_show = Local::now().signed_duration_since::<Local>(summary.start).num_seconds();
-    if time_to_show >= 0 {
-      std::thread::sleep(Duration::seconds(1).to_std()?);
-
-      let force_events = ForceEvents {
-        force_events: vec![ForceEvent {
-          switch_event: Some(SwitchEvent {
-            device: config.device,
-            output: config.output,
-          }),
-          automation_override: None,
-        }],
+      asrun::error(&err);
+      warn!("DEGRADED: could not switch output {}! {}", channel.output, err);
+    }
+
+    info!("sleeping until end");
+    let mut armed = summary.clone();
+    let mut end = end;
+    loop {
+      let changes = match watch_until(config, channel, &armed, skew, end)? {
+        Woken::Due => break,
+        Woken::Changed(changes) => changes,
+        Woken::Operator => {
+          for request in control::take(channel.output) {
+            let result = operate(config, channel, controls, &request, &event, following, io_since);
+            let decision = controls.decide(channel, &event, force_fallback);
+            record_request(channel, &event, &decision, &request, &result);
+
+            match result {
+              Ok(Then::Reframe) => {
+                asrun::update(|record| {
+                  record.template = decision.template().map(|x| x.name.clone());
+                  record.level = decision.level();
+                  record.decision = decision.to_string();
+                });
+                report(channel, State::OnAir, Some(ShowStatus::new(&event)), next.clone(), Some(&decision));
+
+                // Rendered somewhere new, since the browser source is still
+                // loading from the old copy until the scene changes.
+                let rendered = tempdir()?;
+                frame(view, vi_source, channel, &event, &decision, rendered.path())?;
+                drop(std::mem::replace(&mut dir, rendered));
+              }
+              Ok(Then::SetupIo) => {
+                asrun::update(|record| {
+                  record.actual_end = Some(Local::now());
+                  record.errors.push("cut short, the operator set up I/O again".to_string());
+                });
+                asrun::finish();
+                return Ok(());
+              }
+              _ => {}
+            }
+          }
+          continue;
+        }
       };
 
-      let client = reqwest::blocking::Client::new();
-      let url = format!("https://{}/CablecastAPI/v1/forceevents", config.cablecast_url);
-      let resp = client.post(&url)
-        .basic_auth(&config.username, Some(&config.password))
-        .json(&force_events)
-        .send()?;
+      // A show running long or short just moves the end. Anything else means
+      // this isn't the show on air any more, so start over from the schedule.
+      for change in changes {
+        match change {
+          Change::EndMoved { to, .. } => {
+            armed.end = to;
+            let program_end = schedule::program_end(&armed, content_length);
+            end = program_end - skew;
+            asrun::update(|record| record.scheduled_end = program_end);
+            info!("Holding until {}", channel.format_time(&program_end, "%a, %d %b %Y %H:%M:%S %Z"));
+          }
+          change => {
+            info!("Re-arming");
+            asrun::update(|record| {
+              record.actual_end = Some(Local::now());
+              record.errors.push(format!("cut short, {}", change));
+            });
+            asrun::finish();
+            continue 'events;
+          }
+        }
+      }
+    }
+
+    asrun::update(|record| record.actual_end = Some(Local::now()));
+    asrun::finish();
+
+    std::thread::sleep(Duration::seconds(10).to_std()?);
+  }
+}
+
+fn channel_loop(view: View, live: Live, mut channel: ChannelSettings, track: u32, force_fallback: bool) {
+  logging::set_channel(&channel.name());
+  let mut io = setup_io(&view, &channel, track);
+
+  // Kept across I/O being set up again, so a hold or forced fallback isn't
+  // dropped along the way.
+  let mut controls = Controls::default();
+
+  loop {
+    if let Ok((_, Some(output))) = &io {
+      output.force_stop();
+    }
+
+    if let Some((_, current)) = live.channel(channel.output) {
+      channel = current;
+    }
+
+    io = setup_io(&view, &channel, track);
+    status::set_io(channel.output, io.as_ref().ok().cloned());
 
-      if !resp.status().is_success() {
-        return Err(Box::new(ForceEventFailed));
+    if let Ok((vi_source, _)) = &io {
+      let res = show_loop(&view, vi_source, &live, &channel, &mut controls, force_fallback);
+      if let Err(err) = res {
+        asrun::error(&err);
+        asrun::finish();
+        status::set_error(channel.output, err.as_ref());
+
+        if let Err(err) = fallback(&view, vi_source, &channel, &err.to_string()) {
+          error!("Fallback failed! {}", err);
+        }
+        error!("{}", err);
+        info!("Waiting for 1 minute");
+        control::wait(channel.output, Duration::minutes(1).to_std().unwrap());
+        continue;
       }
-    } else {
-      let client = reqwest::blocking::Client::new();
-
-      let force_events = ForceEvents {
-        force_events: vec![ForceEvent {
-          switch_event: None,
-          automation_override: Some(AutomationOverride {
-            r#override: true,
-            output: config.output,
-            do_last_switch_on_resume: false,
-          }),
-        }],
-      };
+    } else if let Err(err) = &io {
+      status::set_error(channel.output, err.as_ref());
+      error!("I/O setup failed! {}", err);
+      info!("Waiting for 1 minute");
+      control::wait(channel.output, Duration::minutes(1).to_std().unwrap());
+      continue;
+    }
+  }
+}
+
+extern {
+  fn scissors_run_qt();
+}
+
+fn start_obs() -> Result<(), Box<dyn Error>> {
+  obs::forward_logs();
+  info!("obs version {}", obs::get_version_string()?);
+
+  assert!(obs::startup("en-US", None, None)?);
+
+  unsafe {
+    scissors_run_qt();
+  }
+
+  let reset_video = unsafe {
+    obs::obs_reset_video(Box::into_raw(Box::new(obs::obs_video_info {
+      graphics_module: "libobs-d3d11\0".as_ptr() as *const c_char,
+      fps_num: 30000,
+      fps_den: 1001,
+      base_width: 1920,
+      base_height: 1080,
+      output_width: 1920,
+      output_height: 1080,
+      output_format: obs::video_format_VIDEO_FORMAT_NV12,
+      adapter: 0,
+      gpu_conversion: true,
+      colorspace: obs::video_colorspace_VIDEO_CS_DEFAULT,
+      range: obs::video_range_type_VIDEO_RANGE_DEFAULT,
+      scale_type: obs::obs_scale_type_OBS_SCALE_BICUBIC,
+    })))
+  };
 
-      let resp = client.post(&url)
-        .basic_auth(&config.username, Some(&config.password))
-        .json(&force_events)
-        .send()?;
+  assert!(reset_video == obs::OBS_VIDEO_SUCCESS as i32);
 
-      if !resp.status().is_success() {
-        return Err(Box::new(ForceEventFailed));
+  assert!(unsafe {
+    obs::obs_reset_audio(Box::into_raw(Box::new(
+      obs::obs_audio_info {
+        samples_per_sec: 48000,
+        speakers: obs::speaker_layout_SPEAKERS_STEREO,
       }
+    )))
+  });
 
-      let until_start = summary.start.signed_duration_since(Local::now());
-      std::thread::sleep(until_start.to_std()?);
-
-      let force_events = ForceEvents {
-        force_events: vec![ForceEvent {
-          switch_event: Some(SwitchEvent {
-            device: config.device,
-            output: config.output,
-          }),
-          automation_override: None,
-        }],
-      };
+  obs::load_all_modules();
+  obs::post_load_modules();
+
+  Ok(())
+}
+
+#[derive(StructOpt)]
+#[structopt(name = "scissors", about = "Frames Cablecast shows with HTML templates through OBS")]
+struct Args {
+  /// Config file to use instead of Documents/scissors-config.json
+  #[structopt(long, global = true, parse(from_os_str))]
+  config: Option<PathBuf>,
+
+  /// How much to log: off, error, warn, info, debug or trace
+  #[structopt(long, global = true, default_value = "info")]
+  log_level: LevelFilter,
+
+  /// Run without the preview window
+  #[structopt(long, global = true)]
+  headless: bool,
+
+  /// Log force events instead of sending them
+  #[structopt(long, global = true)]
+  dry_run: bool,
+
+  #[structopt(subcommand)]
+  command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+  /// Play out the schedule, which is what scissors does without a command
+  Run,
+  /// Write a config file by picking from what Cablecast and OBS report
+  Init,
+  /// List the DeckLink inputs and outputs OBS can see
+  ListDevices,
+  /// List each channel's upcoming shows and how each will be played out
+  Schedule,
+  /// Check the templates for every project in the config
+  ValidateTemplates,
+  /// Show a project's template in the preview window with sample details
+  PreviewTemplate {
+    project: i64,
+
+    /// Use the 4:3 template instead of the 16:9 one
+    #[structopt(long = "4x3")]
+    is_4by3: bool,
+
+    #[structopt(long, default_value = "Sample Show Title")]
+    title: String,
+
+    /// Folder to find the template in instead of Documents/scissors-templates
+    #[structopt(long, parse(from_os_str))]
+    templates: Option<PathBuf>,
+  },
+  /// Check the config file and print it as scissors will run it
+  CheckConfig,
+  /// Print the config as scissors will run it, with defaults filled in and
+  /// secrets hidden
+  PrintConfig,
+  /// Play out with every channel held on its fallback scene, without
+  /// switching any outputs
+  ForceFallback,
+  /// Send a test alert to every webhook and email address in the config
+  TestAlerts,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+  let args = Args::from_args();
+  logging::init(args.log_level);
+
+  let path = args.config.clone().unwrap_or_else(config::default_path);
+  match args.command {
+    None | Some(Command::Run) => run(&path, args.headless, args.dry_run, false),
+    Some(Command::ForceFallback) => run(&path, args.headless, args.dry_run, true),
+    Some(Command::Init) => {
+      start_obs()?;
+      wizard::run(&path)
+    }
+    Some(Command::ListDevices) => list_devices(),
+    Some(Command::Schedule) => print_schedule(&path),
+    Some(Command::ValidateTemplates) => validate_templates(&path),
+    Some(Command::PreviewTemplate { project, is_4by3, title, templates }) => {
+      let root = templates.unwrap_or_else(config::default_template_root);
+      preview_template(&Template::find(&root, project, is_4by3), &title)
+    }
+    Some(Command::CheckConfig) => check_config(&path),
+    Some(Command::PrintConfig) => {
+      println!("{}", config::effective(&config::load(&path)?)?);
+      Ok(())
+    }
+    Some(Command::TestAlerts) => test_alerts(&path),
+  }
+}
 
-      let resp = client.post(&url)
-        .basic_auth(&config.username, Some(&config.password))
-        .json(&force_events)
-        .send()?;
+fn check_config(path: &Path) -> Result<(), Box<dyn Error>> {
+  match config::load(path) {
+    Ok(config) => {
+      println!("{}", config::effective(&config)?);
+      Ok(())
+    }
+    Err(err) => {
+      println!("{}", err);
+      std::process::exit(1);
+    }
+  }
+}
 
-      if !resp.status().is_success() {
-        return Err(Box::new(ForceEventFailed));
+fn test_alerts(path: &Path) -> Result<(), Box<dyn Error>> {
+  let config = config::load(path)?;
+  let now = Local::now();
+  let alert = alerts::Alert {
+    kind: alerts::Kind::Test,
+    subject: "scissors".to_string(),
+    resolved: false,
+    message: "This is a test alert from scissors test-alerts.".to_string(),
+    since: now,
+    at: now,
+  };
+
+  let results = alerts::send(&config.alerts, &alert);
+  if results.is_empty() {
+    println!("No webhooks or email are set up under alerts in the config");
+    std::process::exit(1);
+  }
+
+  let mut failed = false;
+  for (sink, result) in results {
+    match result {
+      Ok(()) => println!("{}: sent", sink),
+      Err(err) => {
+        failed = true;
+        println!("{}: {}", sink, err);
       }
+    }
+  }
+
+  if failed {
+    std::process::exit(1);
+  }
+
+  Ok(())
+}
+
+fn print_devices(kind: &str, properties: Result<obs::Properties, Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+  println!("DeckLink {}:", kind);
+
+  let devices = wizard::decklink_devices(properties)?;
+  if devices.is_empty() {
+    println!("  none found");
+  }
+
+  for (name, hash) in devices {
+    println!("  {}  {}", hash, name);
+  }
+
+  Ok(())
+}
+
+fn list_devices() -> Result<(), Box<dyn Error>> {
+  start_obs()?;
+
+  print_devices("inputs", obs::source_properties("decklink-input"))?;
+  print_devices("outputs", obs::output_properties("decklink_output"))
+}
+
+fn print_schedule(path: &Path) -> Result<(), Box<dyn Error>> {
+  let config = config::load(path)?;
+  let schedule = schedule::fetch(&config)?;
+  if schedule.degraded {
+    println!("Cablecast is unreachable, this is the cached schedule");
+  }
+
+  for channel in &config.channels {
+    let skew = clock_skew(&config, schedule.offset);
+
+    let mut rows = vec![["Start", "End", "Title", "Project", "Aspect", "Template", "Level", "Why"].iter().map(|x| x.to_string()).collect::<Vec<_>>()];
+    for summary in schedule.upcoming(channel, skew) {
+      let start = channel.format_time(&summary.start, "%a %b %d %H:%M");
+      let end = channel.format_time(&summary.end, "%H:%M");
 
-      std::thread::sleep(Duration::seconds(10).to_std()?);
-
-      let force_events = ForceEvents {
-        force_events: vec![ForceEvent {
-          switch_event: None,
-          automation_override: Some(AutomationOverride {
-            r#override: false,
-            output: config.output,
-            do_last_switch_on_resume: false,
-          }),
-        }],
+      let event = match schedule.event(summary) {
+        Ok(event) => event,
+        Err(err) => {
+          rows.push(vec![start, end, format!("show {}", summary.show), String::new(), String::new(), String::new(), "2".to_string(), err.to_string()]);
+          continue;
+        }
       };
 
-      let resp = client.post(&url)
-        .basic_auth(&config.username, Some(&config.password))
-        .json(&force_events)
-        .send()?;
+      let decision = template::decide(channel, &event, false);
+      rows.push(vec![
+        start,
+        end,
+        event.show.cg_title.clone(),
+        event.show.project.map_or(String::new(), |x| x.to_string()),
+        if event.is_4by3() { "4:3" } else { "16:9" }.to_string(),
+        decision.template().map_or(String::new(), |x| x.name.clone()),
+        decision.level().to_string(),
+        decision.to_string(),
+      ]);
+    }
+
+    let widths: Vec<usize> = (0..rows[0].len())
+      .map(|i| rows.iter().map(|row| row[i].chars().count()).max().unwrap())
+      .collect();
+
+    println!();
+    println!("[{}]", channel.name());
+    for row in &rows {
+      let cells: Vec<String> = row.iter().zip(&widths).map(|(cell, width)| format!("{:width$}", cell, width = width)).collect();
+      println!("{}", cells.join("  ").trim_end());
+    }
+  }
+
+  Ok(())
+}
+
+fn validate_templates(path: &Path) -> Result<(), Box<dyn Error>> {
+  let config = config::load(path)?;
+
+  let mut failed = false;
+  for channel in &config.channels {
+    let root = channel.template_root();
+    println!("[{}] templates in {}", channel.name(), root.to_str().unwrap());
+
+    let mut projects: Vec<&i64> = channel.projects.iter().collect();
+    projects.sort();
+    for project in projects {
+      let templates = [Template::find(&root, *project, false), Template::find(&root, *project, true)];
+
+      // A project only needs the aspects it airs in, but needs at least one.
+      if !templates.iter().any(Template::exists) {
+        failed = true;
+        println!("  project {}: no templates, every show will play on fallback", project);
+        continue;
+      }
+
+      for template in templates.iter() {
+        if !template.exists() {
+          println!("  {}: missing, shows in this aspect will play on fallback", template.name);
+          continue;
+        }
+
+        let problems = template.problems();
+        if problems.is_empty() {
+          println!("  {}: ok", template.name);
+        }
 
-      if !resp.status().is_success() {
-        return Err(Box::new(ForceEventFailed));
+        for problem in problems {
+          failed = true;
+          println!("  {}: {}", template.name, problem);
+        }
       }
     }
+  }
 
-    println!("sleeping until end");
-    let until_end = summary.end.signed_duration_since(Local::now()) + Duration::seconds(10);
-    std::thread::sleep(until_end.to_std()?);
+  if failed {
+    std::process::exit(1);
   }
+
+  Ok(())
 }
 
-extern {
-  fn scissors_run_qt();
+fn preview_template(template: &Template, title: &str) -> Result<(), Box<dyn Error>> {
+  if !template.exists() {
+    println!("Could not find {}", template.html.to_str().unwrap());
+    std::process::exit(1);
+  }
+
+  start_obs()?;
+
+  // A flat grey stands in for the DeckLink input so the video's spot in the
+  // frame is easy to see.
+  let settings = Data::new()?;
+  settings.set_int("color", 0xFF80_8080)?;
+  settings.set_int("width", 1920)?;
+  settings.set_int("height", 1080)?;
+  let video = Source::new("color_source", "preview video", Some(&settings), None)?;
+
+  let dir = tempdir()?;
+  let event_date = Local::now().format("%B %d, %Y").to_string();
+  let scene = template_scene(&video, "preview", template, dir.path(), title, &event_date)?;
+
+  let view = View::new()?;
+  view.set_source(0, &scene.get_source()?);
+
+  preview_window(Some(view), || {})
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-  {
-    println!("obs version {}", obs::get_version_string()?);
-
-    assert!(obs::startup("en-US", None, None)?);
-
-    unsafe {
-      scissors_run_qt();
-    }
-
-    let reset_video = unsafe {
-      obs::obs_reset_video(Box::into_raw(Box::new(obs::obs_video_info {
-        graphics_module: "libobs-d3d11\0".as_ptr() as *const c_char,
-        fps_num: 30000,
-        fps_den: 1001,
-        base_width: 1920,
-        base_height: 1080,
-        output_width: 1920,
-        output_height: 1080,
-        output_format: obs::video_format_VIDEO_FORMAT_NV12,
-        adapter: 0,
-        gpu_conversion: true,
-        colorspace: obs::video_colorspace_VIDEO_CS_DEFAULT,
-        range: obs::video_range_type_VIDEO_RANGE_DEFAULT,
-        scale_type: obs::obs_scale_type_OBS_SCALE_BICUBIC,
-      })))
-    };
-
-    assert!(reset_video == obs::OBS_VIDEO_SUCCESS as i32);
-
-    assert!(unsafe {
-      obs::obs_reset_audio(Box::into_raw(Box::new(
-        obs::obs_audio_info {
-          samples_per_sec: 48000,
-          speakers: obs::speaker_layout_SPEAKERS_STEREO,
+/// Opens the preview window showing `preview`, running until it's closed.
+fn preview_window(preview: Option<View>, on_close: impl Fn() + 'static) -> Result<(), Box<dyn Error>> {
+  let event_loop = EventLoop::new();
+  let window = WindowBuilder::new()
+    .with_title("scissors")
+    .with_inner_size(PhysicalSize::new(1600, 900))
+    .build(&event_loop)?;
+
+  let display = obs::Display::new(Box::into_raw(Box::new(obs::gs_init_data {
+    window: obs::gs_window { hwnd: window.hwnd() },
+    cx: 1920,
+    cy: 1080,
+    format: obs::gs_color_format_GS_BGRA,
+    zsformat: obs::gs_zstencil_format_GS_ZS_NONE,
+    adapter: 0,
+    num_backbuffers: 0,
+  })), 0xBABABA)?;
+
+  let mut draw = move |_x, _y| {
+    if let Some(preview) = &preview {
+      preview.render();
+    }
+  };
+  display.add_draw_callback(&mut draw);
+
+  event_loop.run(move |event, _, control_flow| {
+    *control_flow = ControlFlow::Wait;
+
+    match event {
+      Event::WindowEvent {
+        event: WindowEvent::CloseRequested,
+        window_id,
+      } if window_id == window.id() => {
+        on_close();
+        *control_flow = ControlFlow::Exit
+      }
+
+      Event::WindowEvent {
+        event: WindowEvent::Resized(size),
+        window_id,
+      } if window_id == window.id() => {
+        if size.width as f32 / size.height as f32 > 16.0 / 9.0 {
+          display.resize((1080.0 * (size.width as f32 / size.height as f32)) as u32, 1080);
+        } else {
+          display.resize(1920, (1920.0 * (size.height as f32 / size.width as f32)) as u32);
         }
-      )))
-    });
+      }
+      _ => (),
+    }
+  });
+}
 
-    obs::load_all_modules();
-    obs::post_load_modules();
+fn run(path: &Path, headless: bool, dry_run: bool, force_fallback: bool) -> Result<(), Box<dyn Error>> {
+  {
+    start_obs()?;
 
     // let item = item.clone();
     // let filter = filter.map_or(None, |x| Some(x.clone()));
@@ -496,87 +1109,73 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     std::thread::sleep(Duration::seconds(1).to_std()?);
 
-    let mut path = dirs::document_dir().unwrap();
-    path.push("scissors-config.json");
-    if !path.exists() {
-      panic!("Could not find config file");
-    }
+    let live = Live::new(config::load(path)?, path.to_path_buf(), dry_run);
+    live.watch();
 
-    let config: Settings = serde_json::from_reader(File::open(path)?)?;
+    let config = live.settings();
+    logging::configure(&config.log, true)?;
+    alerts::start(live.clone());
 
-    std::thread::spawn(move || {
-      let mut io = setup_io(&config);
+    if config.http.enabled {
+      if let Err(err) = status::serve(&config.http.bind, live.clone()) {
+        error!("Failed to serve status on {}! {}", config.http.bind, err);
+      }
 
-      loop {
-        if let Ok((_, Some(output))) = &io {
-          output.force_stop();
-        }
+      if let Err(err) = events::serve(&config.http.events_bind) {
+        error!("Failed to serve events on {}! {}", config.http.events_bind, err);
+      }
+    }
 
-        io = setup_io(&config);
+    if config.dry_run {
+      info!("Dry run, force events will be logged instead of sent");
+    }
 
-        if let Ok((vi_source, output)) = &io {
-          let res = show_loop(&vi_source, &config);
-          if let Err(err) = res {
-            if let Err(err) = fallback(&vi_source) {
-              println!("Fallback failed! {}", err);
-            }
-            println!("Error: {}", err);
-            println!("Waiting for 1 minute");
-            std::thread::sleep(Duration::minutes(1).to_std().unwrap());
-            continue;
-          }
-        } else if let Err(err) = &io {
-          println!("I/O setup failed!");
-          println!("Error: {}", err);
-          println!("Waiting for 1 minute");
-          std::thread::sleep(Duration::minutes(1).to_std().unwrap());
-          continue;
-        }
+    if force_fallback {
+      info!("Holding every channel on fallback, no outputs will be switched");
+    }
+
+    if let Err(err) = cablecast::release_stale_overrides(&config) {
+      error!("Failed to release stale automation overrides! {}", err);
+    }
+
+    let shutdown_live = live.clone();
+    ctrlc::set_handler(move || {
+      if let Err(err) = cablecast::release_stale_overrides(&shutdown_live.settings()) {
+        error!("Failed to release automation overrides! {}", err);
       }
-    });
+      std::process::exit(0);
+    })?;
+
+    let mut views = Vec::new();
+    for (track, channel) in config.channels.iter().enumerate() {
+      let view = View::new()?;
+      views.push(view.clone());
+
+      status::register(channel);
+
+      let live = live.clone();
+      let channel = channel.clone();
+      // Each channel gets its own audio track. The config check keeps there
+      // from being more channels than tracks.
+      let track = track as u32;
+      std::thread::spawn(move || channel_loop(view, live, channel, track, force_fallback));
+    }
 
-    let event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
-      .with_title("scissors")
-      .with_inner_size(PhysicalSize::new(1600, 900))
-      .build(&event_loop)?;
-
-    let display = obs::Display::new(Box::into_raw(Box::new(obs::gs_init_data {
-      window: obs::gs_window { hwnd: window.hwnd() },
-      cx: 1920,
-      cy: 1080,
-      format: obs::gs_color_format_GS_BGRA,
-      zsformat: obs::gs_zstencil_format_GS_ZS_NONE,
-      adapter: 0,
-      num_backbuffers: 0,
-    })), 0xBABABA)?;
+    status::watch_decklink();
 
-    display.add_draw_callback(&mut |_x, _y| {
-      obs::render_main_texture();
-    });
+    if headless {
+      // The channel threads do all the work, and Ctrl+C shuts down.
+      loop {
+        std::thread::park();
+      }
+    }
 
-    event_loop.run(move |event, _, control_flow| {
-      *control_flow = ControlFlow::Wait;
-
-      match event {
-        Event::WindowEvent {
-          event: WindowEvent::CloseRequested,
-          window_id,
-        } if window_id == window.id() => *control_flow = ControlFlow::Exit,
-
-        Event::WindowEvent {
-          event: WindowEvent::Resized(size),
-          window_id,
-        } if window_id == window.id() => {
-          if size.width as f32 / size.height as f32 > 16.0 / 9.0 {
-            display.resize((1080.0 * (size.width as f32 / size.height as f32)) as u32, 1080);
-          } else {
-            display.resize(1920, (1920.0 * (size.height as f32 / size.width as f32)) as u32);
-          }
-        }
-        _ => (),
+    // The preview window shows the first channel.
+    preview_window(views.first().cloned(), move || {
+      if let Err(err) = cablecast::release_stale_overrides(&live.settings()) {
+        error!("Failed to release automation override! {}", err);
       }
-    });
+    })
   }
 
   // unsafe {
@@ -587,29 +1186,30 @@ fn main() -> Result<(), Box<dyn Error>> {
   // Ok(())
 }
 
-fn setup_io(config: &Settings) -> Result<(Source, Option<Output>), Box<dyn Error>> {
-  let vi_source = Source::new("decklink-input", "video", None, None);
+fn setup_io(view: &View, channel: &ChannelSettings, track: u32) -> Result<(Source, Option<Output>), Box<dyn Error>> {
+  let source_name = format!("{} video", channel.name());
+  let vi_source = Source::new("decklink-input", &source_name, None, None);
   let vi_source = if let Ok(vi_source) = vi_source {
     let props = vi_source.properties()?;
     let prop = props.get("device_hash");
     if let Ok(prop) = prop {
       if prop.list_item_count() != 1 {
         for i in 0..prop.list_item_count() {
-          println!("{}", prop.list_item_name(i)?);
-          println!("{}", prop.list_item_string(i)?);
+          debug!("{}", prop.list_item_name(i)?);
+          debug!("{}", prop.list_item_string(i)?);
         }
 
         let dname = prop.list_item_name(1)?;
         let mut dstr = prop.list_item_string(1)?.to_string();
 
-        println!("Using if config not set: {}", dname);
-        println!("Using if config not set: {}", dstr);
+        debug!("Using if config not set: {}", dname);
+        debug!("Using if config not set: {}", dstr);
 
-        if let Some(input) = &config.decklink_input {
+        if let Some(input) = &channel.decklink_input {
           dstr = input.clone();
         }
 
-        println!("Using: {}", dstr);
+        info!("Using: {}", dstr);
 
         let settings = Data::new()?;
         // settings.set_string("device_name", dname)?;
@@ -626,43 +1226,44 @@ fn setup_io(config: &Settings) -> Result<(Source, Option<Output>), Box<dyn Error
         let settings = Data::new()?;
         settings.set_string("file", "../../../1080img.jpg")?;
 
-        Source::new("image_source", "video", Some(&settings), None)?
+        Source::new("image_source", &source_name, Some(&settings), None)?
       }
     } else {
       let settings = Data::new()?;
       settings.set_string("file", "../../../1080img.jpg")?;
 
-      Source::new("image_source", "video", Some(&settings), None)?
+      Source::new("image_source", &source_name, Some(&settings), None)?
     }
   } else {
     let settings = Data::new()?;
     settings.set_string("file", "../../../1080img.jpg")?;
 
-    Source::new("image_source", "video", Some(&settings), None)?
+    Source::new("image_source", &source_name, Some(&settings), None)?
   };
+  vi_source.set_audio_track(track);
 
-  let output = Output::new("decklink_output", "decklink output", None, None);
+  let output = Output::new("decklink_output", &format!("{} decklink output", channel.name()), None, None);
   if let Ok(output) = &output {
     let props = vi_source.properties()?;
     let prop = props.get("device_hash");
     if let Ok(prop) = prop {
       let prop_count = prop.list_item_count();
       for i in 0..prop_count {
-        println!("{}", prop.list_item_name(i)?);
-        println!("{}", prop.list_item_string(i)?);
+        debug!("{}", prop.list_item_name(i)?);
+        debug!("{}", prop.list_item_string(i)?);
       }
 
       let dname = prop.list_item_name(prop_count - 1)?;
       let mut dstr = prop.list_item_string(prop_count - 1)?.to_string();
 
-      println!("Output using if config not set: {}", dname);
-      println!("Output using if config not set: {}", dstr);
+      debug!("Output using if config not set: {}", dname);
+      debug!("Output using if config not set: {}", dstr);
 
-      if let Some(output) = &config.decklink_output {
+      if let Some(output) = &channel.decklink_output {
         dstr = output.clone();
       }
 
-      println!("Output using: {}", dstr);
+      info!("Output using: {}", dstr);
 
       let settings = Data::new()?;
       // settings.set_string("device_name", dname)?;
@@ -671,6 +1272,7 @@ fn setup_io(config: &Settings) -> Result<(Source, Option<Output>), Box<dyn Error
       settings.set_int("mode_id", 12)?;
 
       output.update(Some(&settings));
+      output.set_view(view, track);
 
       assert!(output.start());
     }
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use prometheus::{
+  Encoder, TextEncoder, HistogramVec, IntCounterVec, CounterVec, IntGauge, IntGaugeVec,
+  register_histogram_vec, register_int_counter_vec, register_counter_vec, register_int_gauge, register_int_gauge_vec,
+};
+
+use crate::events::Event;
+use crate::obs;
+use crate::status;
+
+lazy_static! {
+  static ref CABLECAST_REQUEST_SECONDS: HistogramVec = register_histogram_vec!(
+    "scissors_cablecast_request_seconds",
+    "How long Cablecast API requests took, by endpoint",
+    &["endpoint"]
+  ).unwrap();
+
+  static ref CABLECAST_REQUEST_ERRORS: IntCounterVec = register_int_counter_vec!(
+    "scissors_cablecast_request_errors_total",
+    "Cablecast API requests that failed or got an error status, by endpoint",
+    &["endpoint"]
+  ).unwrap();
+
+  static ref FORCE_EVENTS: IntCounterVec = register_int_counter_vec!(
+    "scissors_force_events_total",
+    "Force events by whether they succeeded in the end, retries included, or were only logged by a dry run",
+    &["channel", "result"]
+  ).unwrap();
+
+  static ref SWITCH_DELAY_SECONDS: HistogramVec = register_histogram_vec!(
+    "scissors_switch_delay_seconds",
+    "Time from a show's scheduled start to its output switching",
+    &["channel"],
+    vec![0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0]
+  ).unwrap();
+
+  static ref TEMPLATE_RENDER_SECONDS: HistogramVec = register_histogram_vec!(
+    "scissors_template_render_seconds",
+    "How long putting a template scene together took",
+    &["channel"]
+  ).unwrap();
+
+  static ref FALLBACK_SECONDS: CounterVec = register_counter_vec!(
+    "scissors_fallback_seconds_total",
+    "Time spent on the fallback scene",
+    &["channel"]
+  ).unwrap();
+
+  static ref OBS_LAGGED_FRAMES: IntGauge = register_int_gauge!(
+    "scissors_obs_lagged_frames",
+    "Frames OBS took too long to render since it started"
+  ).unwrap();
+
+  static ref OBS_SKIPPED_FRAMES: IntGauge = register_int_gauge!(
+    "scissors_obs_skipped_frames",
+    "Frames the OBS video output skipped since it started"
+  ).unwrap();
+
+  static ref OBS_TOTAL_FRAMES: IntGauge = register_int_gauge!(
+    "scissors_obs_total_frames",
+    "Frames OBS rendered since it started"
+  ).unwrap();
+
+  static ref DECKLINK_DROPPED_FRAMES: IntGaugeVec = register_int_gauge_vec!(
+    "scissors_decklink_output_dropped_frames",
+    "Frames the DeckLink output dropped since it was last started",
+    &["channel"]
+  ).unwrap();
+
+  static ref DECKLINK_TOTAL_FRAMES: IntGaugeVec = register_int_gauge_vec!(
+    "scissors_decklink_output_total_frames",
+    "Frames the DeckLink output played since it was last started",
+    &["channel"]
+  ).unwrap();
+
+  // When each channel went to its fallback scene, or when its time there
+  // was last counted.
+  static ref ON_FALLBACK: Mutex<HashMap<String, Instant>> = Mutex::new(HashMap::new());
+}
+
+/// Counts a Cablecast API request that was sent at `sent`.
+pub fn cablecast_request(endpoint: &str, sent: Instant, ok: bool) {
+  CABLECAST_REQUEST_SECONDS.with_label_values(&[endpoint]).observe(sent.elapsed().as_secs_f64());
+  if !ok {
+    CABLECAST_REQUEST_ERRORS.with_label_values(&[endpoint]).inc();
+  }
+}
+
+/// How late a switch was, negative if it was early.
+pub fn switch_delay(channel: &str, delay: chrono::Duration) {
+  SWITCH_DELAY_SECONDS.with_label_values(&[channel]).observe(delay.num_milliseconds() as f64 / 1000.0);
+}
+
+pub fn template_rendered(channel: &str, took: Duration) {
+  TEMPLATE_RENDER_SECONDS.with_label_values(&[channel]).observe(took.as_secs_f64());
+}
+
+/// Counts what the metrics care about from the event feed.
+pub fn observe(channel: Option<&str>, event: &Event) {
+  let channel = channel.unwrap_or_default();
+  match event {
+    Event::ForceEventSucceeded { dry_run: true, .. } => FORCE_EVENTS.with_label_values(&[channel, "dry_run"]).inc(),
+    Event::ForceEventSucceeded { .. } => FORCE_EVENTS.with_label_values(&[channel, "success"]).inc(),
+    Event::ForceEventFailed { .. } => FORCE_EVENTS.with_label_values(&[channel, "failure"]).inc(),
+    Event::FallbackEntered { .. } => {
+      ON_FALLBACK.lock().unwrap().entry(channel.to_string()).or_insert_with(Instant::now);
+    }
+    // A show left alone isn't on the fallback scene as far as the output
+    // goes, since the output was never switched.
+    Event::TemplateActivated { .. } | Event::ShowUntouched { .. } => {
+      if let Some(since) = ON_FALLBACK.lock().unwrap().remove(channel) {
+        FALLBACK_SECONDS.with_label_values(&[channel]).inc_by(since.elapsed().as_secs_f64());
+      }
+    }
+    _ => {}
+  }
+}
+
+// Counts the time channels have been on fallback so far, so a channel stuck
+// there doesn't look fine until it leaves.
+fn count_fallback() {
+  let now = Instant::now();
+  for (channel, since) in ON_FALLBACK.lock().unwrap().iter_mut() {
+    FALLBACK_SECONDS.with_label_values(&[channel]).inc_by(now.duration_since(*since).as_secs_f64());
+    *since = now;
+  }
+}
+
+/// Everything in Prometheus' text format, with the OBS and DeckLink numbers
+/// read as of now.
+pub fn render() -> Result<(String, String), prometheus::Error> {
+  count_fallback();
+
+  OBS_LAGGED_FRAMES.set(obs::lagged_frames() as i64);
+  OBS_SKIPPED_FRAMES.set(obs::skipped_frames() as i64);
+  OBS_TOTAL_FRAMES.set(obs::total_frames() as i64);
+
+  for channel in status::snapshot().channels {
+    if let Some(decklink) = channel.decklink {
+      DECKLINK_DROPPED_FRAMES.with_label_values(&[&channel.name]).set(decklink.output_frames_dropped as i64);
+      DECKLINK_TOTAL_FRAMES.with_label_values(&[&channel.name]).set(decklink.output_total_frames as i64);
+    }
+  }
+
+  let encoder = TextEncoder::new();
+  let mut buffer = Vec::new();
+  encoder.encode(&prometheus::gather(), &mut buffer)?;
+
+  Ok((encoder.format_type().to_string(), String::from_utf8_lossy(&buffer).into_owned()))
+}
@@ -12,6 +12,8 @@ use std::error::Error;
 use std::ptr::{null, null_mut};
 use std::mem::MaybeUninit;
 use std::ffi::{CStr, CString, c_void};
+use std::sync::Arc;
+use log::{log, Level};
 
 #[derive(Debug, Clone)]
 pub struct NullError;
@@ -200,11 +202,31 @@ impl Source {
     }
   }
 
+  pub fn width(&self) -> u32 {
+    unsafe {
+      obs_source_get_width(self.ptr)
+    }
+  }
+
+  pub fn height(&self) -> u32 {
+    unsafe {
+      obs_source_get_height(self.ptr)
+    }
+  }
+
   pub fn filter_add(&self, filter: &Source) {
     unsafe {
       obs_source_filter_add(self.ptr, filter.ptr);
     }
   }
+
+  /// Mixes the source's audio into only the given track, out of
+  /// `MAX_AUDIO_MIXES`.
+  pub fn set_audio_track(&self, track: u32) {
+    unsafe {
+      obs_source_set_audio_mixers(self.ptr, 1 << track);
+    }
+  }
 }
 
 impl From<*mut obs_source_t> for Source {
@@ -411,6 +433,33 @@ impl Output {
       obs_output_force_stop(self.ptr)
     }
   }
+
+  pub fn active(&self) -> bool {
+    unsafe {
+      obs_output_active(self.ptr)
+    }
+  }
+
+  pub fn frames_dropped(&self) -> i32 {
+    unsafe {
+      obs_output_get_frames_dropped(self.ptr)
+    }
+  }
+
+  pub fn total_frames(&self) -> i32 {
+    unsafe {
+      obs_output_get_total_frames(self.ptr)
+    }
+  }
+
+  /// Feeds the output video from `view` instead of the main mix, and audio
+  /// from `track` alone, so channels don't hear each other's inputs.
+  pub fn set_view(&self, view: &View, track: u32) {
+    unsafe {
+      obs_output_set_media(self.ptr, view.inner.video, obs_get_audio());
+      obs_output_set_mixer(self.ptr, track as size_t);
+    }
+  }
 }
 
 impl From<*mut obs_output_t> for Output {
@@ -436,6 +485,67 @@ impl Drop for Output {
 
 unsafe impl Send for Output {}
 
+struct ViewInner {
+  ptr: *mut obs_view_t,
+  video: *mut video_t,
+}
+
+impl Drop for ViewInner {
+  fn drop(&mut self) {
+    unsafe {
+      obs_view_remove(self.ptr);
+      obs_view_destroy(self.ptr);
+    }
+  }
+}
+
+unsafe impl Send for ViewInner {}
+unsafe impl Sync for ViewInner {}
+
+/// A separately mixed video output, so each channel can feed its own output.
+///
+/// Views aren't reference counted by obs, so clones share the same view and
+/// it is torn down when the last one is dropped.
+#[derive(Clone)]
+pub struct View {
+  inner: Arc<ViewInner>,
+}
+
+impl View {
+  pub fn new() -> Result<Self, Box<dyn Error>> {
+    let ptr = unsafe {
+      obs_view_create()
+    };
+
+    if ptr == null_mut() {
+      return Err(Box::new(NullError));
+    }
+
+    let video = unsafe {
+      obs_view_add(ptr)
+    };
+
+    if video == null_mut() {
+      unsafe { obs_view_destroy(ptr) }
+      Err(Box::new(NullError))
+    } else {
+      Ok(Self { inner: Arc::new(ViewInner { ptr, video }) })
+    }
+  }
+
+  pub fn set_source(&self, channel: u32, source: &Source) {
+    unsafe {
+      obs_view_set_source(self.inner.ptr, channel, source.ptr);
+    }
+  }
+
+  pub fn render(&self) {
+    unsafe {
+      obs_view_render(self.inner.ptr);
+    }
+  }
+}
+
 pub struct Display {
   ptr: *mut obs_display_t,
 }
@@ -500,6 +610,54 @@ pub fn get_version_string() -> Result<&'static str, Box<dyn Error>> {
   }
 }
 
+// libobs formats its messages printf style, so they're formatted on the C side
+// into a buffer the same size libobs uses for its own log output.
+unsafe extern "C" fn forward_log(lvl: std::os::raw::c_int, msg: *const std::os::raw::c_char, args: va_list, _param: *mut c_void) {
+  let mut buf = [0 as std::os::raw::c_char; 4096];
+  scissors_vsnprintf(buf.as_mut_ptr(), buf.len() as size_t, msg, args);
+  let text = CStr::from_ptr(buf.as_ptr()).to_string_lossy();
+
+  let level = match lvl as u32 {
+    LOG_ERROR => Level::Error,
+    LOG_WARNING => Level::Warn,
+    LOG_INFO => Level::Info,
+    _ => Level::Debug,
+  };
+
+  // Unwinding out of a callback libobs called is undefined behaviour.
+  let _ = std::panic::catch_unwind(|| {
+    log!(target: "obs", level, "{}", text.trim_end());
+  });
+}
+
+/// Sends everything libobs logs into our log, under the `obs` target, instead
+/// of libobs' default handler.
+pub fn forward_logs() {
+  unsafe {
+    base_set_log_handler(Some(forward_log), null_mut());
+  }
+}
+
+/// Frames that took too long to render since OBS started.
+pub fn lagged_frames() -> u32 {
+  unsafe {
+    obs_get_lagged_frames()
+  }
+}
+
+/// Frames the video output skipped since OBS started, because it fell behind.
+pub fn skipped_frames() -> u32 {
+  unsafe {
+    video_output_get_skipped_frames(obs_get_video())
+  }
+}
+
+pub fn total_frames() -> u32 {
+  unsafe {
+    obs_get_total_frames()
+  }
+}
+
 pub fn startup(locale: &str, module_config_path: Option<&str>, store: Option<*mut profiler_name_store_t>) -> Result<bool, Box<dyn Error>> {
   let locale = CString::new(locale)?;
   let mut _module_config_path_string = CString::new("")?;
@@ -514,6 +672,34 @@ pub fn startup(locale: &str, module_config_path: Option<&str>, store: Option<*mu
   }
 }
 
+/// Properties of a source type, without creating a source of that type.
+pub fn source_properties(id: &str) -> Result<Properties, Box<dyn Error>> {
+  let id = CString::new(id)?;
+  let ptr = unsafe {
+    obs_get_source_properties(id.as_ptr())
+  };
+
+  if ptr == null_mut() {
+    Err(Box::new(NullError))
+  } else {
+    Ok(Properties::from(ptr))
+  }
+}
+
+/// Properties of an output type, without creating an output of that type.
+pub fn output_properties(id: &str) -> Result<Properties, Box<dyn Error>> {
+  let id = CString::new(id)?;
+  let ptr = unsafe {
+    obs_get_output_properties(id.as_ptr())
+  };
+
+  if ptr == null_mut() {
+    Err(Box::new(NullError))
+  } else {
+    Ok(Properties::from(ptr))
+  }
+}
+
 pub fn set_output_source(index: u32, source: &Source) {
   unsafe {
     obs_set_output_source(index, source.ptr);
@@ -0,0 +1,427 @@
+use std::fmt;
+use std::error::Error;
+use std::fs::File;
+use std::path::PathBuf;
+use log::warn;
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, FixedOffset, Local, Duration, TimeZone};
+use tempfile::NamedTempFile;
+
+use crate::cablecast::{self, EventSummaries, EventSummary, Show, DigitalFile, Reel, Media};
+use crate::config::{Settings, ChannelSettings};
+use crate::status;
+use crate::events;
+use crate::alerts;
+
+#[derive(Debug, Clone)]
+pub struct EventSummaryMissing;
+
+impl fmt::Display for EventSummaryMissing {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "the event summary for the channel is missing")
+  }
+}
+
+impl Error for EventSummaryMissing {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct ShowMissing;
+
+impl fmt::Display for ShowMissing {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "the show for the current event is missing")
+  }
+}
+
+impl Error for ShowMissing {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct DigitalFileMissing;
+
+impl fmt::Display for DigitalFileMissing {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "the digital file for the show is missing")
+  }
+}
+
+impl Error for DigitalFileMissing {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedSchedule {
+  fetched_at: DateTime<Local>,
+  summaries: EventSummaries,
+}
+
+/// The schedule as last seen, either straight from Cablecast or from the
+/// on-disk cache when Cablecast couldn't be reached.
+#[derive(Debug)]
+pub struct Schedule {
+  pub summaries: EventSummaries,
+  pub offset: Option<Duration>,
+  pub degraded: bool,
+}
+
+/// The next event on a channel, with the show and file it plays.
+pub struct Event<'a> {
+  pub summary: &'a EventSummary,
+  pub show: &'a Show,
+  pub file: &'a DigitalFile,
+  pub reels: Vec<&'a Reel>,
+  pub media: Vec<&'a Media>,
+}
+
+impl<'a> Event<'a> {
+  pub fn is_4by3(&self) -> bool {
+    self.file.aspect_ratio == 1
+  }
+
+  /// How long a reel runs. One without a length runs from its cue to the
+  /// end of its media's content.
+  pub fn reel_length(&self, reel: &Reel) -> Option<Duration> {
+    reel.length().or_else(|| {
+      let media = self.media.iter().find(|x| Some(x.id) == reel.media)?;
+      Some(media.out_point()? - reel.in_point())
+    })
+  }
+
+  /// How long the show's content actually runs, if Cablecast knows how long
+  /// every reel is.
+  pub fn content_length(&self) -> Option<Duration> {
+    if self.reels.is_empty() {
+      return None;
+    }
+
+    self.reels.iter().try_fold(Duration::zero(), |total, reel| Some(total + self.reel_length(reel)?))
+  }
+
+  /// When the program content ends, which can be well before the end of the
+  /// slot it was scheduled into.
+  pub fn program_end(&self) -> DateTime<FixedOffset> {
+    program_end(self.summary, self.content_length())
+  }
+
+  /// When each reel after the first one starts, as far as the reels' lengths
+  /// are known.
+  pub fn cue_points(&self) -> Vec<DateTime<FixedOffset>> {
+    let mut at = self.summary.start;
+    let mut cue_points = Vec::new();
+    for reel in self.reels.iter().take(self.reels.len().saturating_sub(1)) {
+      at = match self.reel_length(reel) {
+        Some(length) => at + length,
+        None => break,
+      };
+      cue_points.push(at);
+    }
+
+    cue_points
+  }
+}
+
+pub fn program_end(summary: &EventSummary, content_length: Option<Duration>) -> DateTime<FixedOffset> {
+  match content_length {
+    Some(length) => std::cmp::min(summary.end, summary.start + length),
+    None => summary.end,
+  }
+}
+
+/// How an armed event differs in a freshly fetched schedule.
+#[derive(Debug, Clone)]
+pub enum Change {
+  Cancelled,
+  Preempted { show: i64 },
+  StartMoved { from: DateTime<FixedOffset>, to: DateTime<FixedOffset> },
+  EndMoved { from: DateTime<FixedOffset>, to: DateTime<FixedOffset> },
+}
+
+impl fmt::Display for Change {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Change::Cancelled => write!(f, "the event was cancelled"),
+      Change::Preempted { show } => write!(f, "show {} was scheduled ahead of it", show),
+      Change::StartMoved { from, to } => write!(f, "the start moved from {} to {}", from.to_rfc3339(), to.to_rfc3339()),
+      Change::EndMoved { from, to } => write!(f, "the end moved from {} to {}", from.to_rfc3339(), to.to_rfc3339()),
+    }
+  }
+}
+
+fn cache_path() -> PathBuf {
+  let mut path = dirs::document_dir().unwrap();
+  path.push("scissors-schedule-cache.json");
+  path
+}
+
+// Written through a temporary file so a crash mid-write, or another channel
+// saving at the same time, can't leave a half written cache behind.
+fn save_cache(summaries: &EventSummaries, fetched_at: DateTime<Local>) -> Result<(), Box<dyn Error>> {
+  let path = cache_path();
+  let file = NamedTempFile::new_in(path.parent().unwrap())?;
+  serde_json::to_writer(&file, &CachedSchedule {
+    fetched_at,
+    summaries: summaries.clone(),
+  })?;
+  file.persist(path)?;
+
+  Ok(())
+}
+
+fn load_cache() -> Result<CachedSchedule, Box<dyn Error>> {
+  Ok(serde_json::from_reader(File::open(cache_path())?)?)
+}
+
+/// Fetches the schedule from Cablecast, falling back to the last one saved if
+/// Cablecast is unreachable.
+pub fn fetch(config: &Settings) -> Result<Schedule, Box<dyn Error>> {
+  match cablecast::event_summaries(config) {
+    Ok((summaries, offset)) => {
+      status::record_poll(None, false);
+      alerts::cablecast_polled(true);
+      events::publish(events::Event::ScheduleFetched { events: summaries.event_summaries.len(), degraded: false });
+      let fetched_at = Local::now();
+      if let Err(err) = save_cache(&summaries, fetched_at) {
+        warn!("Failed to save schedule cache! {}", err);
+      }
+
+      Ok(Schedule { summaries, offset, degraded: false })
+    }
+    Err(err) => {
+      let cache = match load_cache() {
+        Ok(cache) => cache,
+        Err(_) => {
+          status::record_poll(Some(err.as_ref()), false);
+          alerts::cablecast_polled(false);
+          return Err(err);
+        }
+      };
+
+      status::record_poll(Some(err.as_ref()), true);
+      alerts::cablecast_polled(false);
+      events::publish(events::Event::ScheduleFetched { events: cache.summaries.event_summaries.len(), degraded: true });
+
+      warn!("DEGRADED: Cablecast is unreachable ({}), using the schedule cached at {}", err, cache.fetched_at.to_rfc2822());
+      Ok(Schedule {
+        summaries: cache.summaries,
+        offset: None,
+        degraded: true,
+      })
+    }
+  }
+}
+
+impl Schedule {
+  /// Finds the channel's earliest event whose content hasn't ended yet, going
+  /// by our clock corrected by `skew`.
+  pub fn next_event(&self, channel: &ChannelSettings, skew: Duration) -> Result<Event<'_>, Box<dyn Error>> {
+    self.next_event_at(channel, Local::now() + skew)
+  }
+
+  /// Every event on the channel whose content hasn't ended yet, soonest
+  /// first.
+  pub fn upcoming(&self, channel: &ChannelSettings, skew: Duration) -> Vec<&EventSummary> {
+    self.upcoming_at(channel, Local::now() + skew)
+  }
+
+  // `now` is Cablecast's time, so shows are compared with it as they are.
+  fn next_event_at<Tz: TimeZone>(&self, channel: &ChannelSettings, now: DateTime<Tz>) -> Result<Event<'_>, Box<dyn Error>> {
+    let summary = self.upcoming_at(channel, now).into_iter().next().ok_or(EventSummaryMissing)?;
+    self.event(summary)
+  }
+
+  // A show that ran short is over once its content is, so the rest of its
+  // slot waits on fallback for the next show instead of arming it again.
+  fn upcoming_at<Tz: TimeZone>(&self, channel: &ChannelSettings, now: DateTime<Tz>) -> Vec<&EventSummary> {
+    let mut summaries: Vec<&EventSummary> = self.summaries.event_summaries.iter()
+      .filter(|x| x.location == channel.location && x.channel == channel.channel)
+      .filter(|x| self.event(x).map_or(x.end, |event| event.program_end()) > now)
+      .collect();
+    summaries.sort_by_key(|x| x.start);
+
+    summaries
+  }
+
+  /// Looks up the show and file an event plays.
+  pub fn event<'a>(&'a self, summary: &'a EventSummary) -> Result<Event<'a>, Box<dyn Error>> {
+    let show = self.summaries.shows.iter().find(|x| x.id == summary.show).ok_or(ShowMissing)?;
+    let file = self.summaries.digital_files.iter().find(|x| x.show == summary.show).ok_or(DigitalFileMissing)?;
+    let reels: Vec<&Reel> = show.reels.iter()
+      .filter_map(|id| self.summaries.reels.iter().find(|x| x.id == *id))
+      .collect();
+    let media = self.summaries.media.iter()
+      .filter(|x| reels.iter().any(|reel| reel.media == Some(x.id)))
+      .collect();
+
+    Ok(Event { summary, show, file, reels, media })
+  }
+
+  /// Compares the event we armed against this schedule's version of it.
+  pub fn changes(&self, channel: &ChannelSettings, skew: Duration, armed: &EventSummary) -> Vec<Change> {
+    // The same show can air again later in the day, so take the airing
+    // closest to the one we armed.
+    let current = self.summaries.event_summaries.iter()
+      .filter(|x| x.location == channel.location && x.channel == channel.channel && x.show == armed.show)
+      .min_by_key(|x| x.start.signed_duration_since(armed.start).num_seconds().abs());
+    let current = match current {
+      Some(current) => current,
+      None => return vec![Change::Cancelled],
+    };
+
+    let mut changes = Vec::new();
+    if let Ok(next) = self.next_event(channel, skew) {
+      if next.summary.show != armed.show && next.summary.start < current.start {
+        changes.push(Change::Preempted { show: next.summary.show });
+      }
+    }
+
+    if current.start != armed.start {
+      changes.push(Change::StartMoved { from: armed.start, to: current.start });
+    }
+
+    if current.end != armed.end {
+      changes.push(Change::EndMoved { from: armed.end, to: current.end });
+    }
+
+    changes
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::config::tests::{time, channel};
+
+  fn schedule(events: &[(i64, &str, &str)]) -> Schedule {
+    let event_summaries = events.iter()
+      .map(|(show, start, end)| EventSummary { location: 1, channel: 2, show: *show, start: time(start), end: time(end) })
+      .collect();
+    let shows = events.iter()
+      .map(|(show, start, _)| Show { id: *show, cg_title: format!("Show {}", show), project: None, event_date: time(start), reels: Vec::new() })
+      .collect();
+    let digital_files = events.iter()
+      .map(|(show, _, _)| DigitalFile { show: *show, aspect_ratio: 0 })
+      .collect();
+
+    Schedule {
+      summaries: EventSummaries { event_summaries, shows, digital_files, reels: Vec::new(), media: Vec::new() },
+      offset: None,
+      degraded: false,
+    }
+  }
+
+  // A late show running 23:30 to 00:30, followed by one starting after
+  // midnight.
+  fn across_midnight() -> Schedule {
+    schedule(&[
+      (11, "2020-03-08T00:30:00-06:00", "2020-03-08T01:30:00-06:00"),
+      (10, "2020-03-07T23:30:00-06:00", "2020-03-08T00:30:00-06:00"),
+    ])
+  }
+
+  #[test]
+  fn show_is_next_before_midnight() {
+    let schedule = across_midnight();
+    let upcoming = schedule.upcoming_at(&channel(None), time("2020-03-07T23:00:00-06:00"));
+    assert_eq!(upcoming.iter().map(|x| x.show).collect::<Vec<_>>(), vec![10, 11]);
+  }
+
+  #[test]
+  fn show_stays_on_air_past_midnight() {
+    let schedule = across_midnight();
+    let event = schedule.next_event_at(&channel(None), time("2020-03-08T00:10:00-06:00")).unwrap();
+    assert_eq!(event.show.id, 10);
+    assert_eq!(event.summary.end, time("2020-03-08T00:30:00-06:00"));
+  }
+
+  #[test]
+  fn next_show_follows_once_it_ends() {
+    let schedule = across_midnight();
+    let event = schedule.next_event_at(&channel(None), time("2020-03-08T00:30:00-06:00")).unwrap();
+    assert_eq!(event.show.id, 11);
+  }
+
+  #[test]
+  fn midnight_is_the_same_in_any_offset() {
+    // 06:10 UTC is 00:10 in Chicago, so the late show is still on.
+    let schedule = across_midnight();
+    let event = schedule.next_event_at(&channel(None), time("2020-03-08T06:10:00Z")).unwrap();
+    assert_eq!(event.show.id, 10);
+  }
+
+  #[test]
+  fn nothing_left_after_the_last_show() {
+    let schedule = across_midnight();
+    assert!(schedule.upcoming_at(&channel(None), time("2020-03-08T02:00:00-06:00")).is_empty());
+    assert!(schedule.next_event_at(&channel(None), time("2020-03-08T02:00:00-06:00")).is_err());
+  }
+
+  fn with_reels(reels: Vec<Reel>, media: Vec<Media>) -> Schedule {
+    let mut schedule = across_midnight();
+    let show = schedule.summaries.shows.iter_mut().find(|x| x.id == 10).unwrap();
+    show.reels = reels.iter().map(|x| x.id).collect();
+    schedule.summaries.reels = reels;
+    schedule.summaries.media = media;
+    schedule
+  }
+
+  #[test]
+  fn content_ends_with_the_last_reel() {
+    // The second reel has no length of its own, so it runs to its media's
+    // out point.
+    let schedule = with_reels(vec![
+      Reel { id: 1, media: Some(5), cue: Some(0), length: Some(1200) },
+      Reel { id: 2, media: Some(6), cue: Some(30), length: None },
+    ], vec![
+      Media { id: 6, name: "Part 2".to_string(), in_point: Some(30), out_point: Some(1830), length: Some(1900) },
+    ]);
+
+    let event = schedule.next_event_at(&channel(None), time("2020-03-07T23:00:00-06:00")).unwrap();
+    assert_eq!(event.content_length(), Some(Duration::seconds(3000)));
+    assert_eq!(event.program_end(), time("2020-03-08T00:20:00-06:00"));
+    assert_eq!(event.cue_points(), vec![time("2020-03-07T23:50:00-06:00")]);
+  }
+
+  #[test]
+  fn unknown_reel_length_keeps_the_scheduled_end() {
+    let schedule = with_reels(vec![
+      Reel { id: 1, media: None, cue: None, length: Some(1200) },
+      Reel { id: 2, media: None, cue: None, length: None },
+    ], Vec::new());
+
+    let event = schedule.next_event_at(&channel(None), time("2020-03-07T23:00:00-06:00")).unwrap();
+    assert_eq!(event.content_length(), None);
+    assert_eq!(event.program_end(), time("2020-03-08T00:30:00-06:00"));
+    assert_eq!(event.cue_points(), vec![time("2020-03-07T23:50:00-06:00")]);
+  }
+
+  #[test]
+  fn next_show_follows_once_the_content_ends() {
+    let schedule = with_reels(vec![
+      Reel { id: 1, media: None, cue: None, length: Some(1800) },
+    ], Vec::new());
+
+    let event = schedule.next_event_at(&channel(None), time("2020-03-07T23:50:00-06:00")).unwrap();
+    assert_eq!(event.show.id, 10);
+
+    // Show 10's content ended at midnight, half an hour into its slot.
+    let upcoming = schedule.upcoming_at(&channel(None), time("2020-03-08T00:00:00-06:00"));
+    assert_eq!(upcoming.iter().map(|x| x.show).collect::<Vec<_>>(), vec![11]);
+  }
+
+  #[test]
+  fn other_channels_are_ignored() {
+    let mut schedule = across_midnight();
+    schedule.summaries.event_summaries[1].channel = 5;
+    let event = schedule.next_event_at(&channel(None), time("2020-03-08T00:10:00-06:00")).unwrap();
+    assert_eq!(event.show.id, 11);
+  }
+}
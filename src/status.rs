@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Cursor;
+use std::sync::Mutex;
+use chrono::{DateTime, FixedOffset, Local};
+use lazy_static::lazy_static;
+use log::{info, error};
+use serde::Serialize;
+use tiny_http::{Server, Response, Header};
+
+use crate::obs::{Source, Output};
+use crate::config::{ChannelSettings, Live};
+use crate::schedule::Event;
+use crate::control::{self, Controls};
+use crate::events::{self, Event as FeedEvent};
+use crate::metrics;
+use crate::alerts::{self, Kind as AlertKind};
+
+/// What a channel is doing right now.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+  Starting,
+  /// The next show is more than a few minutes off.
+  Waiting,
+  /// The next show is about to start and the switch is armed.
+  PreRoll,
+  OnAir,
+  /// The next show isn't one scissors frames, so the output is left alone.
+  Untouched,
+  /// An operator is holding the scene.
+  Held,
+  Error,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ShowStatus {
+  pub id: i64,
+  pub title: String,
+  pub start: DateTime<FixedOffset>,
+  pub end: DateTime<FixedOffset>,
+}
+
+impl ShowStatus {
+  pub fn new(event: &Event) -> ShowStatus {
+    ShowStatus {
+      id: event.show.id,
+      title: event.show.cg_title.clone(),
+      start: event.summary.start,
+      end: event.program_end(),
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Poll {
+  pub at: DateTime<Local>,
+  pub ok: bool,
+  /// Playing out from the cached schedule because Cablecast didn't answer.
+  pub degraded: bool,
+  pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct LastError {
+  pub at: DateTime<Local>,
+  pub message: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct DeckLinkStatus {
+  /// The input is producing frames, which it only does with a signal.
+  pub input_has_video: bool,
+  pub input_width: u32,
+  pub input_height: u32,
+  pub output_active: bool,
+  pub output_frames_dropped: i32,
+  pub output_total_frames: i32,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ChannelStatus {
+  pub name: String,
+  pub output: i64,
+  pub state: State,
+  pub current_show: Option<ShowStatus>,
+  pub next_show: Option<ShowStatus>,
+  pub template: Option<String>,
+  pub level: Option<u8>,
+  pub last_error: Option<LastError>,
+  pub decklink: Option<DeckLinkStatus>,
+  pub controls: Controls,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Status {
+  pub started_at: DateTime<Local>,
+  pub uptime_secs: i64,
+  pub last_poll: Option<Poll>,
+  pub channels: Vec<ChannelStatus>,
+}
+
+// A channel's status along with the I/O it was last set up with, which is
+// checked when the status is asked for rather than kept up to date.
+struct Channel {
+  status: ChannelStatus,
+  io: Option<(Source, Option<Output>)>,
+}
+
+struct Registry {
+  started_at: DateTime<Local>,
+  last_poll: Option<Poll>,
+  channels: Vec<Channel>,
+}
+
+lazy_static! {
+  static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    started_at: Local::now(),
+    last_poll: None,
+    channels: Vec::new(),
+  });
+}
+
+/// Starts reporting on a channel. Channels are told apart by their output.
+pub fn register(channel: &ChannelSettings) {
+  let mut registry = REGISTRY.lock().unwrap();
+  registry.channels.retain(|x| x.status.output != channel.output);
+  registry.channels.push(Channel {
+    status: ChannelStatus {
+      name: channel.name(),
+      output: channel.output,
+      state: State::Starting,
+      current_show: None,
+      next_show: None,
+      template: None,
+      level: None,
+      last_error: None,
+      decklink: None,
+      controls: Controls::default(),
+    },
+    io: None,
+  });
+}
+
+pub fn update(output: i64, f: impl FnOnce(&mut ChannelStatus)) {
+  let mut registry = REGISTRY.lock().unwrap();
+  if let Some(channel) = registry.channels.iter_mut().find(|x| x.status.output == output) {
+    f(&mut channel.status);
+  }
+}
+
+pub fn set_error(output: i64, err: &dyn Error) {
+  let message = err.to_string();
+  update(output, |status| {
+    status.state = State::Error;
+    status.last_error = Some(LastError { at: Local::now(), message });
+  });
+}
+
+pub fn set_io(output: i64, io: Option<(Source, Option<Output>)>) {
+  let mut registry = REGISTRY.lock().unwrap();
+  if let Some(channel) = registry.channels.iter_mut().find(|x| x.status.output == output) {
+    channel.io = io;
+  }
+}
+
+/// Notes how the last schedule fetch from Cablecast went.
+pub fn record_poll(error: Option<&dyn Error>, degraded: bool) {
+  REGISTRY.lock().unwrap().last_poll = Some(Poll {
+    at: Local::now(),
+    ok: error.is_none(),
+    degraded,
+    error: error.map(|x| x.to_string()),
+  });
+}
+
+fn decklink(io: &(Source, Option<Output>)) -> DeckLinkStatus {
+  let (input, output) = io;
+  DeckLinkStatus {
+    input_has_video: input.width() > 0 && input.height() > 0,
+    input_width: input.width(),
+    input_height: input.height(),
+    output_active: output.as_ref().map_or(false, Output::active),
+    output_frames_dropped: output.as_ref().map_or(0, Output::frames_dropped),
+    output_total_frames: output.as_ref().map_or(0, Output::total_frames),
+  }
+}
+
+pub fn snapshot() -> Status {
+  let registry = REGISTRY.lock().unwrap();
+  let now = Local::now();
+
+  Status {
+    started_at: registry.started_at,
+    uptime_secs: now.signed_duration_since(registry.started_at).num_seconds(),
+    last_poll: registry.last_poll.clone(),
+    channels: registry.channels.iter().map(|channel| {
+      let mut status = channel.status.clone();
+      status.decklink = channel.io.as_ref().map(decklink);
+      status
+    }).collect(),
+  }
+}
+
+// How often the DeckLink I/O is checked for the input or output going away.
+const DECKLINK_CHECK_SECS: u64 = 1;
+
+/// Watches every channel's DeckLink I/O from a background thread, publishing
+/// an event when an input loses its signal or an output stops.
+pub fn watch_decklink() {
+  std::thread::spawn(|| {
+    let mut last: HashMap<i64, DeckLinkStatus> = HashMap::new();
+    loop {
+      for channel in snapshot().channels {
+        let decklink = match channel.decklink {
+          Some(decklink) => decklink,
+          None => {
+            last.remove(&channel.output);
+            continue;
+          }
+        };
+
+        if let Some(before) = last.get(&channel.output) {
+          if before.input_has_video && !decklink.input_has_video {
+            events::publish_for(&channel.name, FeedEvent::DeckLinkSignalLost);
+          }
+
+          if before.output_active && !decklink.output_active {
+            events::publish_for(&channel.name, FeedEvent::OutputStopped);
+            alerts::raise(AlertKind::OutputStopped, &channel.name, format!("The DeckLink output for {} stopped", channel.name));
+          } else if !before.output_active && decklink.output_active {
+            alerts::resolve(AlertKind::OutputStopped, &channel.name);
+          }
+        }
+
+        last.insert(channel.output, decklink);
+      }
+
+      std::thread::sleep(std::time::Duration::from_secs(DECKLINK_CHECK_SECS));
+    }
+  });
+}
+
+pub fn json<T: Serialize>(code: u16, value: &T) -> Response<Cursor<Vec<u8>>> {
+  match serde_json::to_string_pretty(value) {
+    Ok(json) => Response::from_string(json)
+      .with_status_code(code)
+      .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap()),
+    Err(err) => Response::from_string(err.to_string()).with_status_code(500),
+  }
+}
+
+/// Serves the status as JSON on `bind` from a background thread, along with
+/// the remote control API.
+pub fn serve(bind: &str, live: Live) -> Result<(), Box<dyn Error>> {
+  let server = Server::http(bind).map_err(|err| err.to_string())?;
+  info!("Serving status on http://{}/status", bind);
+
+  std::thread::spawn(move || {
+    for mut request in server.incoming_requests() {
+      let response = match request.url() {
+        "/" | "/status" => json(200, &snapshot()),
+        "/events.schema.json" => Response::from_string(events::SCHEMA)
+          .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/schema+json"[..]).unwrap()),
+        "/metrics" => match metrics::render() {
+          Ok((content_type, text)) => Response::from_string(text)
+            .with_header(Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap()),
+          Err(err) => Response::from_string(err.to_string()).with_status_code(500),
+        },
+        url if url.starts_with("/control/") => control::handle(&mut request, &live),
+        _ => Response::from_string("not found").with_status_code(404),
+      };
+
+      if let Err(err) = request.respond(response) {
+        error!("Failed to answer a status request! {}", err);
+      }
+    }
+  });
+
+  Ok(())
+}
@@ -0,0 +1,212 @@
+use std::fmt;
+use std::error::Error;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use log::{debug, trace};
+use scraper::{Html, Selector};
+use fs_extra::dir::{copy, CopyOptions};
+
+use crate::config::ChannelSettings;
+use crate::schedule::Event;
+
+#[derive(Debug, Clone)]
+pub struct VideoRectMissing {
+  pub svg: PathBuf,
+}
+
+impl fmt::Display for VideoRectMissing {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} has no #VIDEO rect with a numeric x, y, width and height", self.svg.to_str().unwrap())
+  }
+}
+
+impl Error for VideoRectMissing {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    None
+  }
+}
+
+/// Where the video sits in a template, in 1080p pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct Rect {
+  pub x: f32,
+  pub y: f32,
+  pub width: f32,
+  pub height: f32,
+}
+
+/// A project's template folder for one aspect ratio, such as
+/// `scissors-templates/12-16x9` holding `12-16x9.html` and `12-16x9.svg`.
+#[derive(Debug, Clone)]
+pub struct Template {
+  pub name: String,
+  pub is_4by3: bool,
+  pub folder: PathBuf,
+  pub html: PathBuf,
+  pub svg: PathBuf,
+}
+
+impl Template {
+  pub fn find(root: &Path, project: i64, is_4by3: bool) -> Template {
+    let name = format!("{}-{}", project, if is_4by3 { "4x3" } else { "16x9" });
+    let folder = root.join(&name);
+    trace!("Looking for template {} in {}", name, root.display());
+
+    Template {
+      html: folder.join(format!("{}.html", name)),
+      svg: folder.join(format!("{}.svg", name)),
+      name,
+      is_4by3,
+      folder,
+    }
+  }
+
+  /// Finds a template by a name such as `12-16x9`, or nothing if the name
+  /// isn't one.
+  pub fn named(root: &Path, name: &str) -> Option<Template> {
+    let (project, aspect) = name.split_at(name.find('-')?);
+    let project = project.parse::<i64>().ok()?;
+    match aspect {
+      "-16x9" => Some(Template::find(root, project, false)),
+      "-4x3" => Some(Template::find(root, project, true)),
+      _ => None,
+    }
+  }
+
+  pub fn exists(&self) -> bool {
+    self.html.exists()
+  }
+
+  pub fn video_rect(&self) -> Result<Rect, Box<dyn Error>> {
+    let mut svg = String::new();
+    File::open(&self.svg)?.read_to_string(&mut svg)?;
+
+    let document = Html::parse_fragment(&svg);
+    let selector = Selector::parse("#VIDEO").unwrap();
+    let missing = || VideoRectMissing { svg: self.svg.clone() };
+    let element = document.select(&selector).next().ok_or_else(missing)?;
+    let attr = |name| -> Result<f32, VideoRectMissing> {
+      element.value().attr(name).and_then(|x| x.parse::<f32>().ok()).ok_or_else(missing)
+    };
+
+    let rect = Rect {
+      x: attr("x")?,
+      y: attr("y")?,
+      width: attr("width")?,
+      height: attr("height")?,
+    };
+    trace!("{} puts the video at {:?}", self.name, rect);
+
+    Ok(rect)
+  }
+
+  /// Everything that would stop the template from being used, or nothing if
+  /// it's fine.
+  pub fn problems(&self) -> Vec<String> {
+    let mut problems = Vec::new();
+    if !self.exists() {
+      problems.push(format!("{} is missing", self.html.to_str().unwrap()));
+      return problems;
+    }
+
+    if let Err(err) = std::fs::read_to_string(&self.html) {
+      problems.push(format!("{} can't be read: {}", self.html.to_str().unwrap(), err));
+    }
+
+    if let Err(err) = self.video_rect() {
+      problems.push(err.to_string());
+    }
+
+    problems
+  }
+
+  /// Copies the template into `dir` and fills in the show's details, returning
+  /// the page to load.
+  pub fn render(&self, dir: &Path, cg_title: &str, event_date: &str) -> Result<PathBuf, Box<dyn Error>> {
+    copy(&self.folder, dir, &CopyOptions::new())?;
+
+    let mut html = String::new();
+    File::open(&self.html)?.read_to_string(&mut html)?;
+    html = html.replace("{{cg_title}}", cg_title);
+    html = html.replace("{{event_date}}", event_date);
+
+    let page = dir.join(&self.name).join("template.html");
+    File::create(&page)?.write_all(html.as_bytes())?;
+    debug!("Rendered {} for \"{}\" to {}", self.name, cg_title, page.display());
+
+    Ok(page)
+  }
+}
+
+/// How a show will be played out, from the best case down.
+#[derive(Debug, Clone)]
+pub enum Decision {
+  /// Framed by its template.
+  Template(Template),
+  /// Switched to scissors, but left on the fallback scene because the
+  /// template can't be used.
+  Fallback { template: Template, reason: String },
+  /// Not switched to scissors at all.
+  Untouched { reason: String },
+}
+
+impl Decision {
+  /// How far down from a framed show this is, 0 being framed.
+  pub fn level(&self) -> u8 {
+    match self {
+      Decision::Template(_) => 0,
+      Decision::Fallback { .. } => 1,
+      Decision::Untouched { .. } => 2,
+    }
+  }
+
+  pub fn template(&self) -> Option<&Template> {
+    match self {
+      Decision::Template(template) | Decision::Fallback { template, .. } => Some(template),
+      Decision::Untouched { .. } => None,
+    }
+  }
+}
+
+impl fmt::Display for Decision {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Decision::Template(template) => write!(f, "framed by {}", template.name),
+      Decision::Fallback { reason, .. } => write!(f, "fallback scene, {}", reason),
+      Decision::Untouched { reason } => write!(f, "not switched, {}", reason),
+    }
+  }
+}
+
+/// Decides how the channel plays out an event. `show_loop` and
+/// `scissors schedule` both go through here so they can't disagree.
+pub fn decide(channel: &ChannelSettings, event: &Event, force_fallback: bool) -> Decision {
+  if force_fallback {
+    return Decision::Untouched { reason: "fallback is being forced".to_string() };
+  }
+
+  let project = match event.show.project {
+    Some(project) if channel.projects.contains(&project) => project,
+    Some(project) => return Decision::Untouched { reason: format!("project {} isn't framed on this channel", project) },
+    None => return Decision::Untouched { reason: "the show has no project".to_string() },
+  };
+
+  check(Template::find(&channel.template_root(), project, event.is_4by3()))
+}
+
+/// Frames a show with `template` if it can be used, or falls back.
+pub fn check(template: Template) -> Decision {
+  if !template.exists() {
+    let reason = format!("{} is missing", template.html.to_str().unwrap());
+    debug!("Can't use template {}, {}", template.name, reason);
+    return Decision::Fallback { template, reason };
+  }
+
+  if let Err(err) = template.video_rect() {
+    debug!("Can't use template {}, {}", template.name, err);
+    return Decision::Fallback { template, reason: err.to_string() };
+  }
+
+  Decision::Template(template)
+}
@@ -0,0 +1,258 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{stdin, stdout, Write};
+use std::path::{Path, PathBuf};
+use serde_json::{json, Value};
+
+use crate::obs;
+use crate::cablecast::{self, Named};
+use crate::config;
+
+fn prompt(question: &str) -> Result<String, Box<dyn Error>> {
+  print!("{}: ", question);
+  stdout().flush()?;
+
+  let mut answer = String::new();
+  stdin().read_line(&mut answer)?;
+  Ok(answer.trim().to_string())
+}
+
+fn confirm(question: &str) -> Result<bool, Box<dyn Error>> {
+  Ok(prompt(&format!("{} [y/N]", question))?.eq_ignore_ascii_case("y"))
+}
+
+fn choose(kind: &str, records: &[&Named]) -> Result<i64, Box<dyn Error>> {
+  println!();
+  println!("Available {}:", kind);
+  for record in records {
+    println!("  {:>6}  {}", record.id, record.name);
+  }
+
+  loop {
+    let answer = prompt(&format!("Which {} id", kind))?;
+    match answer.parse::<i64>() {
+      Ok(id) if records.iter().any(|x| x.id == id) => return Ok(id),
+      _ => println!("\"{}\" isn't one of the ids above", answer),
+    }
+  }
+}
+
+fn choose_many(kind: &str, records: &[&Named]) -> Result<Vec<i64>, Box<dyn Error>> {
+  println!();
+  println!("Available {}:", kind);
+  for record in records {
+    println!("  {:>6}  {}", record.id, record.name);
+  }
+
+  'ask: loop {
+    let answer = prompt(&format!("Which {} ids (comma separated)", kind))?;
+    let mut ids = Vec::new();
+    for part in answer.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+      match part.parse::<i64>() {
+        Ok(id) if records.iter().any(|x| x.id == id) => ids.push(id),
+        _ => {
+          println!("\"{}\" isn't one of the ids above", part);
+          continue 'ask;
+        }
+      }
+    }
+
+    if ids.is_empty() {
+      println!("Pick at least one");
+      continue;
+    }
+
+    return Ok(ids);
+  }
+}
+
+// Lists the DeckLink devices obs can see for a source or output type, by the
+// `device_hash` property the config refers to them with.
+pub fn decklink_devices(properties: Result<obs::Properties, Box<dyn Error>>) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+  let properties = match properties {
+    Ok(properties) => properties,
+    Err(_) => return Ok(Vec::new()),
+  };
+
+  let prop = match properties.get("device_hash") {
+    Ok(prop) => prop,
+    Err(_) => return Ok(Vec::new()),
+  };
+
+  let mut devices = Vec::new();
+  for i in 0..prop.list_item_count() {
+    let hash = prop.list_item_string(i)?;
+    if !hash.is_empty() {
+      devices.push((prop.list_item_name(i)?.to_string(), hash.to_string()));
+    }
+  }
+
+  Ok(devices)
+}
+
+// Only a lone channel can leave its devices for scissors to pick, so with
+// more than one a device is `required`.
+fn choose_decklink(kind: &str, devices: &[(String, String)], required: bool) -> Result<Value, Box<dyn Error>> {
+  println!();
+  if devices.is_empty() {
+    if required {
+      return Err(format!("no DeckLink {}s found, and every channel needs its own when there's more than one", kind).into());
+    }
+
+    println!("No DeckLink {}s found, scissors will pick one when it starts", kind);
+    return Ok(Value::Null);
+  }
+
+  println!("DeckLink {}s:", kind);
+  for (i, (name, hash)) in devices.iter().enumerate() {
+    println!("  {:>2}  {} ({})", i + 1, name, hash);
+  }
+
+  let question = if required {
+    format!("Which DeckLink {}", kind)
+  } else {
+    format!("Which DeckLink {} (blank to pick when scissors starts)", kind)
+  };
+
+  loop {
+    let answer = prompt(&question)?;
+    if answer.is_empty() && !required {
+      return Ok(Value::Null);
+    }
+
+    match answer.parse::<usize>() {
+      Ok(i) if i >= 1 && i <= devices.len() => return Ok(Value::from(devices[i - 1].1.clone())),
+      _ => println!("\"{}\" isn't one of the numbers above", answer),
+    }
+  }
+}
+
+// Creates or replaces `path` with only this account able to read it, which
+// is what the config checks before reading a secret from a file.
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &str) -> Result<(), Box<dyn Error>> {
+  use std::os::unix::fs::PermissionsExt;
+
+  std::fs::write(path, contents)?;
+  std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+  Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &str) -> Result<(), Box<dyn Error>> {
+  use std::process::Command;
+
+  std::fs::write(path, contents)?;
+
+  // Drops what the file inherited from its folder, leaving this account the
+  // only one on it.
+  let grant = format!("{}:F", std::env::var("USERNAME")?);
+  let output = Command::new("icacls").arg(path).args(&["/inheritance:r", "/grant:r", &grant]).output()?;
+  if !output.status.success() {
+    return Err(format!("icacls couldn't lock down {}: {}", path.to_str().unwrap(), String::from_utf8_lossy(&output.stdout).trim()).into());
+  }
+
+  Ok(())
+}
+
+// Keeps the password out of the config, in a file next to it that the
+// config reads it from.
+fn save_password(path: &Path, password: &str) -> Result<PathBuf, Box<dyn Error>> {
+  let secret = path.with_file_name("scissors-cablecast-password.txt");
+  write_private(&secret, password)?;
+  Ok(secret)
+}
+
+fn in_location(records: &[Named], location: i64) -> Vec<&Named> {
+  records.iter().filter(|x| x.location.map_or(true, |x| x == location)).collect()
+}
+
+/// Walks through writing `scissors-config.json`, picking everything from
+/// what Cablecast and the local DeckLink cards report.
+pub fn run(path: &Path) -> Result<(), Box<dyn Error>> {
+  if path.exists() && !confirm(&format!("{} already exists, replace it?", path.to_str().unwrap()))? {
+    return Ok(());
+  }
+
+  let cablecast_url = prompt("Cablecast host (for example cablecast.example.org)")?;
+  let username = prompt("Cablecast username")?;
+  let password = rpassword::read_password_from_tty(Some("Cablecast password: "))?;
+
+  let list = |kind: &str| cablecast::named(&cablecast_url, &username, &password, kind);
+  let locations = list("locations")?;
+  let channels = list("channels")?;
+  let outputs = list("outputs")?;
+  let devices = list("devices")?;
+  let projects = list("projects")?;
+
+  let inputs = decklink_devices(obs::source_properties("decklink-input"))?;
+  let decklink_outputs = decklink_devices(obs::output_properties("decklink_output"))?;
+
+  let mut channel_blocks = Vec::new();
+  loop {
+    println!();
+    println!("Channel {}", channel_blocks.len() + 1);
+
+    let location = choose("locations", &locations.iter().collect::<Vec<_>>())?;
+    let channel = choose("channels", &in_location(&channels, location))?;
+    let output = choose("outputs", &in_location(&outputs, location))?;
+    let device = choose("devices", &in_location(&devices, location))?;
+    let channel_projects = choose_many("projects", &projects.iter().collect::<Vec<_>>())?;
+    let required = !channel_blocks.is_empty();
+    let decklink_input = choose_decklink("input", &inputs, required)?;
+    let decklink_output = choose_decklink("output", &decklink_outputs, required)?;
+
+    println!();
+    let name = prompt("Name for this channel (blank for none)")?;
+
+    channel_blocks.push(json!({
+      "name": if name.is_empty() { Value::Null } else { Value::from(name) },
+      "decklink_input": decklink_input,
+      "decklink_output": decklink_output,
+      "location": location,
+      "channel": channel,
+      "output": output,
+      "device": device,
+      "projects": channel_projects,
+    }));
+
+    println!();
+    if !confirm("Add another channel?")? {
+      break;
+    }
+
+    let first = &mut channel_blocks[0];
+    if first["decklink_input"].is_null() || first["decklink_output"].is_null() {
+      println!();
+      println!("With more than one channel, channel 1 needs its own DeckLink devices too");
+      if first["decklink_input"].is_null() {
+        first["decklink_input"] = choose_decklink("input", &inputs, true)?;
+      }
+      if first["decklink_output"].is_null() {
+        first["decklink_output"] = choose_decklink("output", &decklink_outputs, true)?;
+      }
+    }
+  }
+
+  let secret = save_password(path, &password)?;
+  println!();
+  println!("Wrote the password to {}", secret.to_str().unwrap());
+
+  let value = json!({
+    "version": config::VERSION,
+    "cablecast_url": cablecast_url,
+    "username": username,
+    "password": { "file": secret },
+    "channels": channel_blocks,
+  });
+
+  // Run it through the same checks as a config loaded at startup before
+  // anything is written.
+  config::parse(value.clone())?;
+
+  serde_json::to_writer_pretty(File::create(path)?, &value)?;
+  println!();
+  println!("Wrote {}", path.to_str().unwrap());
+
+  Ok(())
+}
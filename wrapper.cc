@@ -1,6 +1,7 @@
 #include "wrapper.h"
 #include "QApplication"
 #include "QThread"
+#include <stdio.h>
 
 typedef std::function<void()> VoidFunc;
 
@@ -30,6 +31,10 @@ extern "C" {
     vec2_set(dst, x, y);
   }
 
+  int scissors_vsnprintf(char *dst, size_t len, const char *format, va_list args) {
+    return vsnprintf(dst, len, format, args);
+  }
+
   void scissors_run_qt() {
     int argc = 1;
     char *argv[] = { "scissors.exe" };
@@ -6,6 +6,8 @@ extern "C" {
 
 __declspec(dllexport) void scissors_vec2_set(struct vec2 *dst, float x, float y);
 
+__declspec(dllexport) int scissors_vsnprintf(char *dst, size_t len, const char *format, va_list args);
+
 __declspec(dllexport) void scissors_run_qt();
 
 #ifdef __cplusplus